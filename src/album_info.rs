@@ -0,0 +1,100 @@
+use crate::api::structs::AlbumMetaResult;
+use crate::structs::{AlbumTrackInfo, ParsedAlbumMeta};
+
+use std::fs::File;
+use std::io::{BufWriter, Error as IoError, Write};
+use std::path::Path;
+
+const INFO_FNAME: &str = "info.txt";
+
+// CUE sheets have no way of escaping double quotes.
+fn cue_escape(value: &str) -> String {
+    value.replace('"', "'")
+}
+
+fn cue_file_type(fname: &str) -> &'static str {
+    if fname.to_lowercase().ends_with(".mp3") {
+        "MP3"
+    } else {
+        "WAVE"
+    }
+}
+
+fn format_duration(secs: u32) -> String {
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, mins, secs)
+    } else {
+        format!("{}:{:02}", mins, secs)
+    }
+}
+
+// One FILE entry per track, as each track is its own file.
+pub fn write_cue(cue_path: &Path, meta: &ParsedAlbumMeta, tracks: &[AlbumTrackInfo]) -> Result<(), IoError> {
+    let f = File::create(cue_path)?;
+    let mut writer = BufWriter::new(f);
+
+    if meta.year > 0 {
+        writeln!(writer, "REM DATE {}", meta.year)?;
+    }
+    if !meta.copyright.is_empty() {
+        writeln!(writer, "REM COPYRIGHT \"{}\"", cue_escape(&meta.copyright))?;
+    }
+    if !meta.upc.is_empty() {
+        writeln!(writer, "CATALOG {}", meta.upc)?;
+    }
+    writeln!(writer, "PERFORMER \"{}\"", cue_escape(&meta.album_artist))?;
+    writeln!(writer, "TITLE \"{}\"", cue_escape(&meta.album_title))?;
+
+    for track in tracks {
+        writeln!(writer, "FILE \"{}\" {}", cue_escape(&track.fname), cue_file_type(&track.fname))?;
+        writeln!(writer, "  TRACK {:02} AUDIO", track.track_num)?;
+        writeln!(writer, "    TITLE \"{}\"", cue_escape(&track.title))?;
+        if !track.artist.is_empty() {
+            writeln!(writer, "    PERFORMER \"{}\"", cue_escape(&track.artist))?;
+        }
+        if !track.isrc.is_empty() {
+            writeln!(writer, "    ISRC {}", track.isrc)?;
+        }
+        writeln!(writer, "    INDEX 01 00:00:00")?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_info(album_path: &Path, album: &AlbumMetaResult, meta: &ParsedAlbumMeta, tracks: &[AlbumTrackInfo]) -> Result<(), IoError> {
+    let f = File::create(album_path.join(INFO_FNAME))?;
+    let mut writer = BufWriter::new(f);
+
+    let participants: Vec<&str> = album.participants.iter().map(|p| p.name.as_str()).collect();
+    let mut qualities: Vec<&str> = tracks.iter().map(|t| t.quality).collect();
+    qualities.sort();
+    qualities.dedup();
+
+    writeln!(writer, "Title:        {}", meta.album_title)?;
+    writeln!(writer, "Participants: {}", participants.join(", "))?;
+    if meta.year > 0 {
+        writeln!(writer, "Year:         {}", meta.year)?;
+    }
+    writeln!(writer, "Copyright:    {}", meta.copyright)?;
+    writeln!(writer, "UPC:          {}", meta.upc)?;
+    writeln!(writer, "Quality:      {}", qualities.join(", "))?;
+    writeln!(writer, "Source:       {}", meta.source_url)?;
+    writeln!(writer)?;
+
+    writeln!(writer, "Tracks:")?;
+    for track in tracks {
+        writeln!(writer, "{:02}. {} ({})", track.track_num, track.title, format_duration(track.duration))?;
+        if !track.artist.is_empty() {
+            writeln!(writer, "    {}", track.artist)?;
+        }
+    }
+
+    let total: u32 = tracks.iter().map(|t| t.duration).sum();
+    writeln!(writer)?;
+    writeln!(writer, "Total length: {}", format_duration(total))?;
+
+    writer.flush()?;
+    Ok(())
+}
@@ -1,20 +1,50 @@
 use crate::api::structs::*;
+use crate::events::{self, status};
+use crate::utils;
 
 use std::collections::HashMap;
 use std::error::Error;
 
 use reqwest::blocking::{Client, Response as ReqwestResp};
-use reqwest::Error as ReqwestErr;
+use reqwest::{Error as ReqwestErr, StatusCode};
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, USER_AGENT, AUTHORIZATION, RANGE, REFERER};
 use reqwest::Url;
 use scraper::{Html, Selector};
-use serde_json::{self, Error as SerdeErr};
+use serde::de::DeserializeOwned;
+use serde_json::{self, Error as SerdeErr, Value};
 
 const BASE_URL: &str = "https://api.idagio.com/";
 const CLIENT_ID: &str = "com.idagio.app.android";
 const IDAGIO_USER_AGENT: &str = "Android 3.8.8 (Build 3080800) [release]";
 const CLIENT_SECRET: &str = "adbisIGrocsUckWyodUj2knedpyepubGurlyeawosShyufJishleseanreBlogIbCefHodCigNafweegyeebraftEdnooshDeavolirdoppEcIassyet9CirIrnofmaj";
 
+// Filter keys as they appear in profile URLs (lowercased), and what the album filter endpoint calls them.
+const ARTIST_FILTER_KEYS: [(&str, &str); 7] = [
+	("composers", "composer"),
+	("conductors", "conductor"),
+	("ensembles", "ensemble"),
+	("instruments", "instrument"),
+	("labels", "label"),
+	("soloists", "soloist"),
+	("works", "work"),
+];
+
+// Site sort orders and their API equivalents.
+const ARTIST_SORT_VALUES: [(&str, &str); 5] = [
+	("alphabetical", "title"),
+	("newest", "newest"),
+	("oldest", "oldest"),
+	("popular", "popularity"),
+	("relevance", "relevance"),
+];
+
+// The whole response is kept alongside the parsed one so it can be saved as-is.
+fn parse_with_raw<T: DeserializeOwned>(resp: ReqwestResp) -> Result<(T, Value), Box<dyn Error>> {
+	let raw: Value = resp.json()?;
+	let parsed: T = serde_json::from_value(raw.clone())?;
+	Ok((parsed, raw))
+}
+
 pub struct IDAGIOClient {
     c: Client,
     pub user_info: UserInfo,
@@ -69,25 +99,31 @@ impl IDAGIOClient {
 		Ok(())
 	}
 
-	pub fn get_album_meta(&mut self, album_slug: &str) -> Result<AlbumMetaResult, ReqwestErr> {
+	pub fn get_album_meta(&mut self, album_slug: &str) -> Result<AlbumMetaResult, Box<dyn Error>> {
 		let url = format!("{}v2.0/albums/{}", BASE_URL, album_slug);
 		let resp = self.c.get(url)
 			.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
 			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
 			.send()?;
 		resp.error_for_status_ref()?;
-		let meta: AlbumMeta = resp.json()?;
+		let (mut meta, raw): (AlbumMeta, Value) = parse_with_raw(resp)?;
+		// Not always part of the response, and needed to find the album again later.
+		if meta.result.slug.is_empty() {
+			meta.result.slug = album_slug.to_string();
+		}
+		meta.result.raw = raw;
 		Ok(meta.result)
 	}
 
-	pub fn get_playlist_meta(&mut self, plist_slug: &str) -> Result<PlaylistMetaResult, ReqwestErr> {
+	pub fn get_playlist_meta(&mut self, plist_slug: &str) -> Result<PlaylistMetaResult, Box<dyn Error>> {
 		let url = format!("{}v2.0/playlists/{}", BASE_URL, plist_slug);
 		let resp = self.c.get(url)
 			.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
 			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
 			.send()?;
 		resp.error_for_status_ref()?;
-		let meta: PlaylistMeta = resp.json()?;
+		let (mut meta, raw): (PlaylistMeta, Value) = parse_with_raw(resp)?;
+		meta.result.raw = raw;
 		Ok(meta.result)
 	}
 
@@ -110,20 +146,27 @@ impl IDAGIOClient {
 	fn filter_artist_params(&mut self, base_url: &str, params: &mut HashMap<String, String>) -> Result<(), Box<dyn Error>> {
 		let url = Url::parse(base_url)?;
 
-		let mut allowed_keys = HashMap::new();
-		allowed_keys.insert("composers", true);
-		allowed_keys.insert("conductors", true);
-		allowed_keys.insert("ensembles", true);
-		allowed_keys.insert("instruments", true);
-		allowed_keys.insert("soloists", true);
-
 		for (k, v) in url.query_pairs() {
-			if allowed_keys.contains_key(&k.as_ref()) {
-				let mut key = k.to_string();
-				key.pop();
-				params.insert(key, v.into_owned());
-			} else {
-				println!("Dropped param: {}.", k);
+			if let Some((_, api_key)) = ARTIST_FILTER_KEYS.iter().find(|(site_key, _)| *site_key == k) {
+				params.insert(api_key.to_string(), v.into_owned());
+				continue;
+			}
+
+			match k.as_ref() {
+				"sort" => match ARTIST_SORT_VALUES.iter().find(|(site_val, _)| *site_val == v) {
+					Some((_, api_val)) => {
+						params.insert("sort".to_string(), api_val.to_string());
+					},
+					None => events::warn(&format!("Dropped param: sort={}.", v)),
+				},
+				"years" | "recordingyears" => match utils::parse_year_range(&v) {
+					Ok((from, to)) => {
+						params.insert("year_from".to_string(), from.to_string());
+						params.insert("year_to".to_string(), to.to_string());
+					},
+					Err(_) => events::warn(&format!("Dropped param: {}={}.", k, v)),
+				},
+				_ => events::warn(&format!("Dropped param: {}.", k)),
 			}
 		}
 
@@ -147,7 +190,7 @@ impl IDAGIOClient {
 		}
 
 		params.insert("artist".to_string(), artist_id_string.to_string());
-		params.insert("sort".to_string(), "relevance".to_string());
+		params.entry("sort".to_string()).or_insert("relevance".to_string());
 
 		loop {
 
@@ -168,7 +211,7 @@ impl IDAGIOClient {
 
 			if let Some(c) = meta.meta.cursor.next.clone() {
 				if meta.meta.cursor.prev.is_none() {
-					println!("Artist has more than 100 albums. Fetching the remaining metadata...")
+					status!("Artist has more than 100 albums. Fetching the remaining metadata...")
 				}
 				cursor_opt = Some(c);
 			} else {
@@ -198,7 +241,124 @@ impl IDAGIOClient {
 			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
 			.send()?;
 		resp.error_for_status_ref()?;
-		let meta: PersonalPlaylistsMeta = resp.json()?;
+		let (mut meta, raw): (PersonalPlaylistsMeta, Value) = parse_with_raw(resp)?;
+		meta.result.raw = raw;
+		Ok(meta.result)
+	}
+
+	pub fn get_personal_plists(&mut self) -> Result<Vec<PersonalPlaylistSummary>, Box<dyn Error>> {
+		let url_no_params = format!("{}v1.0/personal-playlists", BASE_URL);
+		let mut all_meta: Vec<PersonalPlaylistSummary> = Vec::new();
+		let mut params: HashMap<String, String> = HashMap::new();
+
+		loop {
+			let url = Url::parse_with_params(&url_no_params, &params)?;
+			let resp = self.c.get(url)
+				.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
+				.header(CONTENT_TYPE, "application/json; charset=UTF-8")
+				.send()?;
+			resp.error_for_status_ref()?;
+
+			let meta: PersonalPlaylistsListMeta = resp.json()?;
+			all_meta.extend(meta.results);
+
+			match meta.meta.and_then(|m| m.cursor.next) {
+				Some(c) => {
+					params.insert("cursor".to_string(), c);
+				},
+				None => break,
+			}
+		}
+
+		Ok(all_meta)
+	}
+
+	pub fn get_user_profile(&mut self, user_id: &str) -> Result<UserProfileResult, ReqwestErr> {
+		let url = format!("{}v1.0/users/{}/profile", BASE_URL, user_id);
+		let resp = self.c.get(url)
+			.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
+			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
+			.send()?;
+		resp.error_for_status_ref()?;
+		let meta: UserProfileMeta = resp.json()?;
+		Ok(meta.result)
+	}
+
+	pub fn get_recording_meta(&mut self, id: &str) -> Result<RecordingMetaResult, ReqwestErr> {
+		let url = format!("{}v2.0/metadata/recordings/{}", BASE_URL, id);
+		let resp = self.c.get(url)
+			.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
+			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
+			.send()?;
+		resp.error_for_status_ref()?;
+		let meta: RecordingMeta = resp.json()?;
+		Ok(meta.result)
+	}
+
+	// One page at a time; the caller filters and decides when it has enough.
+	pub fn get_work_recordings_meta(&mut self, work_id: &str, cursor: Option<&str>) -> Result<WorkRecordingsMeta, Box<dyn Error>> {
+		let mut params: HashMap<&str, &str> = HashMap::new();
+		params.insert("sort", "relevance");
+		if let Some(c) = cursor {
+			params.insert("cursor", c);
+		}
+
+		let url_no_params = format!("{}v2.0/metadata/works/{}/recordings", BASE_URL, work_id);
+		let url = Url::parse_with_params(&url_no_params, &params)?;
+
+		let resp = self.c.get(url)
+			.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
+			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
+			.send()?;
+		resp.error_for_status_ref()?;
+		let meta: WorkRecordingsMeta = resp.json()?;
+		Ok(meta)
+	}
+
+	// kind is one of albums, tracks, recordings, playlists or artists.
+	pub fn get_collection<T: DeserializeOwned>(&mut self, kind: &str) -> Result<Vec<T>, Box<dyn Error>> {
+		let url_no_params = format!("{}v2.0/collection/{}", BASE_URL, kind);
+		let mut all_meta: Vec<T> = Vec::new();
+		let mut params: HashMap<String, String> = HashMap::new();
+
+		loop {
+			let url = Url::parse_with_params(&url_no_params, &params)?;
+			let resp = self.c.get(url)
+				.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
+				.header(CONTENT_TYPE, "application/json; charset=UTF-8")
+				.send()?;
+			resp.error_for_status_ref()?;
+
+			let meta: CollectionMeta<T> = resp.json()?;
+			all_meta.extend(meta.results);
+
+			match meta.meta.and_then(|m| m.cursor.next) {
+				Some(c) => {
+					params.insert("cursor".to_string(), c);
+				},
+				None => break,
+			}
+		}
+
+		Ok(all_meta)
+	}
+
+	pub fn search(&mut self, query: &str, limit: u32) -> Result<SearchResult, Box<dyn Error>> {
+		let mut params: HashMap<&str, &str> = HashMap::new();
+		let limit_str = limit.to_string();
+
+		params.insert("query", query);
+		params.insert("limit", limit_str.as_str());
+
+		let url_no_params = BASE_URL.to_owned() + "v2.0/search";
+		let url = Url::parse_with_params(&url_no_params, &params)?;
+
+		let resp = self.c.get(url)
+			.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
+			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
+			.send()?;
+		resp.error_for_status_ref()?;
+		let meta: SearchMeta = resp.json()?;
 		Ok(meta.result)
 	}
 
@@ -233,23 +393,34 @@ impl IDAGIOClient {
 	}
 
 	pub fn get_file_resp(&mut self, url: &str, with_range: bool) -> Result<ReqwestResp, ReqwestErr> {
-		let mut req = self.c.get(url);
 		if with_range {
-			req = req.header(RANGE, "bytes=0-")
+			return self.get_file_resp_from(url, 0);
 		}
-		let resp = req.send()?;
+		let resp = self.c.get(url).send()?;
 		resp.error_for_status_ref()?;
 		Ok(resp)
 	}
 
-	pub fn get_video_meta(&mut self, slug: &str) -> Result<VideoMetaResult, ReqwestErr> {
+	pub fn get_file_resp_from(&mut self, url: &str, offset: u64) -> Result<ReqwestResp, ReqwestErr> {
+		let resp = self.c.get(url)
+			.header(RANGE, format!("bytes={}-", offset))
+			.send()?;
+		// Nothing left past the offset. Left to the caller, who knows what's on disk.
+		if offset == 0 || resp.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+			resp.error_for_status_ref()?;
+		}
+		Ok(resp)
+	}
+
+	pub fn get_video_meta(&mut self, slug: &str) -> Result<VideoMetaResult, Box<dyn Error>> {
 		let url = format!("{}livestream-event.v2/{}", BASE_URL, slug);
 		let resp = self.c.get(url)
 			.header(AUTHORIZATION, format!("Bearer  {}", self.user_info.access_token))
 			.header(CONTENT_TYPE, "application/json; charset=UTF-8")
 			.send()?;
 		resp.error_for_status_ref()?;
-		let meta: VideoMeta = resp.json()?;
+		let (mut meta, raw): (VideoMeta, Value) = parse_with_raw(resp)?;
+		meta.result.raw = raw;
 		Ok(meta.result)
 	}
 
@@ -291,4 +462,57 @@ impl IDAGIOClient {
 		let meta: VideoMaster = resp.json()?;
 		Ok(meta)
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn filter(query: &str) -> HashMap<String, String> {
+		let mut params = HashMap::new();
+		let url = format!("{}v2.0/metadata/albums/filter?{}", BASE_URL, query);
+		IDAGIOClient::new().unwrap().filter_artist_params(&url, &mut params).unwrap();
+		params
+	}
+
+	#[test]
+	fn maps_filter_keys() {
+		for (site_key, api_key) in ARTIST_FILTER_KEYS {
+			let params = filter(&format!("{}=123", site_key));
+			assert_eq!(params.len(), 1, "{}", site_key);
+			assert_eq!(params.get(api_key).map(String::as_str), Some("123"), "{}", site_key);
+		}
+	}
+
+	#[test]
+	fn maps_sort_values() {
+		for (site_val, api_val) in ARTIST_SORT_VALUES {
+			let params = filter(&format!("sort={}", site_val));
+			assert_eq!(params.get("sort").map(String::as_str), Some(api_val), "{}", site_val);
+		}
+		assert!(filter("sort=shuffle").is_empty());
+	}
+
+	#[test]
+	fn maps_year_ranges() {
+		for key in ["years", "recordingyears"] {
+			let params = filter(&format!("{}=1970-1980", key));
+			assert_eq!(params.get("year_from").map(String::as_str), Some("1970"), "{}", key);
+			assert_eq!(params.get("year_to").map(String::as_str), Some("1980"), "{}", key);
+
+			let params = filter(&format!("{}=1975", key));
+			assert_eq!(params.get("year_from").map(String::as_str), Some("1975"), "{}", key);
+			assert_eq!(params.get("year_to").map(String::as_str), Some("1975"), "{}", key);
+
+			assert!(filter(&format!("{}=1980-1970", key)).is_empty(), "{}", key);
+			assert!(filter(&format!("{}=soon", key)).is_empty(), "{}", key);
+		}
+	}
+
+	#[test]
+	fn drops_unknown_keys() {
+		let params = filter("composers=1&genre=baroque&composer=2&page=3");
+		assert_eq!(params.len(), 1);
+		assert_eq!(params.get("composer").map(String::as_str), Some("1"));
+	}
 }
\ No newline at end of file
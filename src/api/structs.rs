@@ -1,5 +1,7 @@
 use serde::{Deserialize, Deserializer, Serialize};
 use serde::de::{self, Visitor};
+use serde_json::Value;
+use std::collections::HashMap;
 use std::fmt;
 
 #[derive(Deserialize)]
@@ -89,11 +91,29 @@ fn deserialize_id_as_string<'de, D>(deserializer: D) -> Result<String, D::Error>
     deserializer.deserialize_any(StringOrIntVisitor)
 }
 
+#[derive(Deserialize)]
+pub struct TrackRecording {
+    #[serde(deserialize_with = "deserialize_id_as_string")]
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+pub struct TrackAlbum {
+    pub slug: String,
+}
+
 #[derive(Deserialize)]
 pub struct Track {
     #[serde(deserialize_with = "deserialize_id_as_string")]
     pub id: String,
+    // Only present in playlists.
+    pub album: Option<TrackAlbum>,
+    // Seconds.
+    #[serde(default)]
+    pub duration: u32,
+    pub isrc: Option<String>,
     pub piece: Piece,
+    pub recording: Option<TrackRecording>,
     // pub position: i64,
 }
 
@@ -110,6 +130,10 @@ pub struct AlbumMetaResult {
     pub copyright_year: u16,
     pub image_url: String,
     pub participants: Vec<Participant>,
+    #[serde(skip)]
+    pub raw: Value,
+    #[serde(default)]
+    pub slug: String,
     pub title: String,
     pub track_ids: Vec<String>,
     pub tracks: Vec<Track>,
@@ -152,10 +176,15 @@ pub struct StreamMetaResult {
 pub struct Video {
     pub name: String,
     pub source: String,
+    // Manifest URL for non-Vimeo events.
+    pub url: Option<String>,
+    #[serde(default)]
     pub video_id: String,
 }
 #[derive(Deserialize)]
 pub struct VideoMetaResult {
+    #[serde(skip)]
+    pub raw: Value,
     pub video: Video,
 }
 
@@ -176,18 +205,14 @@ pub struct Files {
 
 #[derive(Deserialize)]
 pub struct Dash {
-    pub cdns: Cdns
+    pub cdns: HashMap<String, Cdn>,
+    pub default_cdn: Option<String>,
 }
 
 #[derive(Deserialize)]
-pub struct Cdns {
-    pub akfire_interconnect_quic: AkfireInterconnectQuic,
-}
-
-#[derive(Deserialize)]
-pub struct AkfireInterconnectQuic {
-    pub avc_url: String,
-    // pub url: String,
+pub struct Cdn {
+    pub avc_url: Option<String>,
+    pub url: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -230,6 +255,9 @@ pub struct Curator {
 pub struct PlaylistMetaResult {
     pub title: String,
     pub curator: Curator,
+    pub image_url: Option<String>,
+    #[serde(skip)]
+    pub raw: Value,
     pub track_ids: Vec<u64>,
     pub tracks: Vec<Track>,
 }
@@ -261,8 +289,15 @@ pub struct ArtistMeta {
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ArtistAlbumsMetaResult {
+    #[serde(default)]
+    pub copyright_year: u16,
+    #[serde(default)]
+    pub is_compilation: bool,
     pub slug: String,
+    #[serde(default)]
+    pub title: String,
 }
 #[derive(Deserialize)]
 pub struct ArtistAlbumsMeta {
@@ -275,10 +310,157 @@ pub struct PersonalPlaylistMetaResult {
     pub id: String,
     pub user_id: String,
     pub title: String,
+    pub image_url: Option<String>,
+    #[serde(skip)]
+    pub raw: Value,
     pub tracks: Vec<Track>,
 }
 
 #[derive(Deserialize)]
 pub struct PersonalPlaylistsMeta {
     pub result: PersonalPlaylistMetaResult,
+}
+
+#[derive(Deserialize)]
+pub struct PersonalPlaylistSummary {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Deserialize)]
+pub struct PersonalPlaylistsListMeta {
+    pub meta: Option<Meta>,
+    pub results: Vec<PersonalPlaylistSummary>,
+}
+
+#[derive(Deserialize)]
+pub struct UserProfileResult {
+    #[serde(default)]
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UserProfileMeta {
+    pub result: UserProfileResult,
+}
+
+#[derive(Deserialize)]
+pub struct Soloist {
+    pub person: Person,
+}
+
+#[derive(Deserialize)]
+pub struct RecordingDate {
+    pub from: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingMetaResult {
+    #[serde(deserialize_with = "deserialize_id_as_string")]
+    pub id: String,
+    pub work: Work,
+    pub conductor: Option<Person>,
+    #[serde(default)]
+    pub ensembles: Vec<Person>,
+    #[serde(default)]
+    pub soloists: Vec<Soloist>,
+    pub recording_date: Option<RecordingDate>,
+    // Not included when listed under a work.
+    #[serde(default)]
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Deserialize)]
+pub struct RecordingMeta {
+    pub result: RecordingMetaResult,
+}
+
+#[derive(Deserialize)]
+pub struct WorkRecordingsMeta {
+    pub meta: Meta,
+    pub results: Vec<RecordingMetaResult>,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionAlbum {
+    pub slug: String,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionRecording {
+    #[serde(deserialize_with = "deserialize_id_as_string")]
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionPlaylist {
+    pub slug: String,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionArtist {
+    pub slug: String,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionMeta<T> {
+    pub meta: Option<Meta>,
+    pub results: Vec<T>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchAlbum {
+    pub slug: String,
+    pub title: String,
+    #[serde(default)]
+    pub participants: Vec<Participant>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchRecording {
+    #[serde(deserialize_with = "deserialize_id_as_string")]
+    pub id: String,
+    pub work: Work,
+    #[serde(default)]
+    pub summary: String,
+}
+
+#[derive(Deserialize)]
+pub struct SearchWork {
+    #[serde(deserialize_with = "deserialize_id_as_string")]
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub authors: Vec<Author>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchArtist {
+    pub slug: String,
+    pub name: String,
+    #[serde(default)]
+    pub functions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchPlaylist {
+    pub slug: String,
+    pub title: String,
+    pub curator: Option<Curator>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct SearchResult {
+    pub albums: Vec<SearchAlbum>,
+    pub recordings: Vec<SearchRecording>,
+    pub works: Vec<SearchWork>,
+    pub artists: Vec<SearchArtist>,
+    pub playlists: Vec<SearchPlaylist>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchMeta {
+    pub result: SearchResult,
 }
\ No newline at end of file
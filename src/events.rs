@@ -0,0 +1,120 @@
+use crate::structs::SearchItem;
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, ThreadId};
+
+use serde::Serialize;
+
+static JSON_MODE: AtomicBool = AtomicBool::new(false);
+// Only events from the thread that started recording, so serve's HTTP thread stays out of it.
+static RECORDED: Mutex<Option<(ThreadId, Vec<Event>)>> = Mutex::new(None);
+
+// Human-readable output goes to stderr in JSON mode so that stdout stays parseable.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::events::json_mode() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub(crate) use status;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    SignedIn {
+        plan: String,
+        premium: bool,
+    },
+    UrlStart {
+        url: String,
+        url_num: usize,
+        url_total: usize,
+    },
+    Metadata {
+        kind: &'static str,
+        title: String,
+        artist: String,
+        path: String,
+        track_total: u16,
+    },
+    TrackStart {
+        track_num: u16,
+        track_total: u16,
+        title: String,
+        artist: String,
+        quality: &'static str,
+    },
+    Progress {
+        downloaded: u64,
+        total: u64,
+    },
+    TrackDone {
+        track_num: u16,
+        path: String,
+        quality: &'static str,
+        bytes: u64,
+        existing: bool,
+    },
+    Warning {
+        message: String,
+    },
+    Error {
+        source: String,
+        message: String,
+    },
+    UrlDone {
+        url: String,
+    },
+    SearchResults {
+        query: String,
+        items: Vec<SearchItem>,
+    },
+}
+
+pub fn set_json_mode(enabled: bool) {
+    JSON_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn json_mode() -> bool {
+    JSON_MODE.load(Ordering::Relaxed)
+}
+
+pub fn emit(event: Event) {
+    if json_mode() {
+        match serde_json::to_string(&event) {
+            Ok(serialised) => println!("{}", serialised),
+            Err(e) => eprintln!("Failed to serialise event.\n{}", e),
+        }
+    }
+
+    if let Ok(mut recorded) = RECORDED.lock() {
+        if let Some((thread_id, events)) = recorded.as_mut() {
+            if *thread_id == thread::current().id() && !matches!(event, Event::Progress { .. }) {
+                events.push(event);
+            }
+        }
+    }
+}
+
+// Collects every emitted event until take_recorded is called.
+pub fn start_recording() {
+    if let Ok(mut recorded) = RECORDED.lock() {
+        *recorded = Some((thread::current().id(), Vec::new()));
+    }
+}
+
+pub fn take_recorded() -> Vec<Event> {
+    match RECORDED.lock() {
+        Ok(mut recorded) => recorded.take().map(|(_, events)| events).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn warn(message: &str) {
+    status!("{}", message);
+    emit(Event::Warning { message: message.to_string() });
+}
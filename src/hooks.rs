@@ -0,0 +1,127 @@
+use crate::events;
+
+use std::error::Error;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use serde::Serialize;
+
+// Sent as JSON on stdin. The main fields are also set as IDAGIO_* env vars for simple scripts.
+#[derive(Serialize)]
+#[serde(tag = "hook", rename_all = "snake_case")]
+pub enum Hook {
+    TrackComplete {
+        path: String,
+        track_num: u16,
+        track_total: u16,
+        title: String,
+        artist: String,
+        album_title: String,
+        album_artist: String,
+        quality: &'static str,
+        isrc: String,
+        track_id: String,
+        url: String,
+    },
+    AlbumComplete {
+        kind: &'static str,
+        path: String,
+        title: String,
+        artist: String,
+        track_paths: Vec<String>,
+        url: String,
+    },
+    UrlFailed {
+        url: String,
+        error: String,
+    },
+}
+
+impl Hook {
+    fn name(&self) -> &'static str {
+        match self {
+            Hook::TrackComplete { .. } => "track_complete",
+            Hook::AlbumComplete { .. } => "album_complete",
+            Hook::UrlFailed { .. } => "url_failed",
+        }
+    }
+
+    fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![("IDAGIO_HOOK", self.name().to_string())];
+        match self {
+            Hook::TrackComplete { path, track_num, title, artist, album_title, url, .. } => vars.extend([
+                ("IDAGIO_PATH", path.clone()),
+                ("IDAGIO_TRACK_NUM", track_num.to_string()),
+                ("IDAGIO_TITLE", title.clone()),
+                ("IDAGIO_ARTIST", artist.clone()),
+                ("IDAGIO_ALBUM", album_title.clone()),
+                ("IDAGIO_URL", url.clone()),
+            ]),
+            Hook::AlbumComplete { kind, path, title, artist, url, .. } => vars.extend([
+                ("IDAGIO_KIND", kind.to_string()),
+                ("IDAGIO_PATH", path.clone()),
+                ("IDAGIO_TITLE", title.clone()),
+                ("IDAGIO_ARTIST", artist.clone()),
+                ("IDAGIO_URL", url.clone()),
+            ]),
+            Hook::UrlFailed { url, error } => vars.extend([
+                ("IDAGIO_URL", url.clone()),
+                ("IDAGIO_ERROR", error.clone()),
+            ]),
+        }
+        vars
+    }
+}
+
+fn shell_command(cmd_line: &str) -> Command {
+    if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", cmd_line]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", cmd_line]);
+        cmd
+    }
+}
+
+fn run_hook(cmd_line: &str, hook: &Hook) -> Result<(), Box<dyn Error>> {
+    let payload = serde_json::to_vec(hook)?;
+
+    // Keep stdout parseable in JSON mode.
+    let stdout = if events::json_mode() {
+        Stdio::from(io::stderr())
+    } else {
+        Stdio::inherit()
+    };
+
+    let mut child = shell_command(cmd_line)
+        .envs(hook.env_vars())
+        .stdin(Stdio::piped())
+        .stdout(stdout)
+        .spawn()?;
+
+    // Hooks that don't read stdin close it early, which is fine.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(&payload);
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        let err_str = format!("bad exit code: {}", status);
+        return Err(err_str.into());
+    }
+    Ok(())
+}
+
+// Hooks run to completion before downloading carries on. Failures only warn.
+pub fn run(cmd_line: &Option<String>, hook: Hook) {
+    let cmd_line = match cmd_line.as_deref().map(str::trim) {
+        Some(c) if !c.is_empty() => c,
+        _ => return,
+    };
+
+    if let Err(e) = run_hook(cmd_line, &hook) {
+        events::warn(&format!("The {} hook failed.\n{}", hook.name(), e));
+    }
+}
@@ -0,0 +1,266 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// Blocks are 400 ms long and start every 100 ms, as per ITU-R BS.1770.
+const SEGMENTS_PER_BLOCK: usize = 4;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+// Gating blocks are kept rather than just the final figure so tracks can be combined into an album.
+pub struct Loudness {
+    pub blocks: Vec<f64>,
+    pub peak: f32,
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+// K-weighting: a high shelf for the head's acoustic effect, then a high pass.
+// Coefficients are derived for any sample rate, same as libebur128.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+struct Meter {
+    filters: Vec<[Biquad; 2]>,
+    segment_len: usize,
+    // Sum of squares per channel for the segment being filled.
+    current: Vec<f64>,
+    current_len: usize,
+    // Mean square per segment, summed over channels.
+    segments: Vec<f64>,
+    peak: f32,
+}
+
+impl Meter {
+    fn new(rate: u32, channels: usize) -> Meter {
+        Meter {
+            filters: (0..channels).map(|_| k_weighting(rate)).collect(),
+            segment_len: (rate / 10).max(1) as usize,
+            current: vec![0.0; channels],
+            current_len: 0,
+            segments: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    // Channels are all weighted equally, which is right for mono and stereo.
+    fn add_frames(&mut self, interleaved: &[f32]) {
+        let channels = self.filters.len();
+        for frame in interleaved.chunks_exact(channels) {
+            for (ch, sample) in frame.iter().enumerate() {
+                self.peak = self.peak.max(sample.abs());
+                let [shelf, high_pass] = &mut self.filters[ch];
+                let filtered = high_pass.process(shelf.process(*sample as f64));
+                self.current[ch] += filtered * filtered;
+            }
+
+            self.current_len += 1;
+            if self.current_len == self.segment_len {
+                let energy: f64 = self.current.iter().map(|sum| sum / self.segment_len as f64).sum();
+                self.segments.push(energy);
+                self.current.iter_mut().for_each(|sum| *sum = 0.0);
+                self.current_len = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> Loudness {
+        let blocks = self.segments
+            .windows(SEGMENTS_PER_BLOCK)
+            .map(|w| w.iter().sum::<f64>() / SEGMENTS_PER_BLOCK as f64)
+            .collect();
+        Loudness { blocks, peak: self.peak }
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// Integrated loudness in LUFS, or None for silence.
+pub fn integrated(blocks: &[f64]) -> Option<f64> {
+    let above_abs: Vec<f64> = blocks.iter()
+        .copied()
+        .filter(|e| *e > 0.0 && energy_to_lufs(*e) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if above_abs.is_empty() {
+        return None;
+    }
+
+    let relative_gate = energy_to_lufs(mean(&above_abs)) + RELATIVE_GATE_LU;
+    let above_rel: Vec<f64> = above_abs.into_iter()
+        .filter(|e| energy_to_lufs(*e) > relative_gate)
+        .collect();
+    if above_rel.is_empty() {
+        return None;
+    }
+    Some(energy_to_lufs(mean(&above_rel)))
+}
+
+pub fn analyse(track_path: &Path) -> Result<Loudness, Box<dyn Error>> {
+    let f = File::open(track_path)?;
+    let mss = MediaSourceStream::new(Box::new(f), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = track_path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut format = probed.format;
+    let track = format.default_track().ok_or("no audio track")?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut meter: Option<Meter> = None;
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(p) => p,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            // A corrupt packet isn't worth giving up on the whole track for.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let needed = decoded.capacity() * channels;
+        if sample_buf.as_ref().is_none_or(|buf| buf.capacity() < needed) {
+            sample_buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+
+        if let Some(buf) = sample_buf.as_mut() {
+            buf.copy_interleaved_ref(decoded);
+            meter.get_or_insert_with(|| Meter::new(spec.rate, channels))
+                .add_frames(buf.samples());
+        }
+    }
+
+    let meter = meter.ok_or("no audio decoded")?;
+    Ok(meter.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lufs_to_energy(lufs: f64) -> f64 {
+        10f64.powf((lufs + 0.691) / 10.0)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.05, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        assert_eq!(integrated(&[]), None);
+        assert_eq!(integrated(&[0.0; 10]), None);
+        assert_eq!(integrated(&[lufs_to_energy(-80.0); 10]), None);
+    }
+
+    #[test]
+    fn steady_blocks_measure_as_themselves() {
+        assert_close(integrated(&[lufs_to_energy(-20.0); 10]).unwrap(), -20.0);
+    }
+
+    #[test]
+    fn absolute_gate_drops_near_silence() {
+        let mut blocks = vec![lufs_to_energy(-20.0); 10];
+        blocks.extend([lufs_to_energy(-75.0); 10]);
+        assert_close(integrated(&blocks).unwrap(), -20.0);
+    }
+
+    #[test]
+    fn relative_gate_drops_quiet_passages() {
+        // -35 is more than 10 LU under the ungated mean, -25 isn't.
+        let mut blocks = vec![lufs_to_energy(-20.0); 10];
+        blocks.extend([lufs_to_energy(-35.0); 10]);
+        assert_close(integrated(&blocks).unwrap(), -20.0);
+
+        let mut blocks = vec![lufs_to_energy(-20.0); 10];
+        blocks.extend([lufs_to_energy(-25.0); 10]);
+        let expected = energy_to_lufs((lufs_to_energy(-20.0) + lufs_to_energy(-25.0)) / 2.0);
+        assert_close(integrated(&blocks).unwrap(), expected);
+    }
+
+    // EBU Tech 3341 test case 1: a 1 kHz stereo sine at -23 dBFS is -23 LUFS.
+    #[test]
+    fn reference_sine_measures_minus_23() {
+        let rate = 48000;
+        let amplitude = 10f64.powf(-23.0 / 20.0);
+        let samples: Vec<f32> = (0..rate * 10)
+            .flat_map(|i| {
+                let sample = (amplitude * (2.0 * PI * 1000.0 * i as f64 / rate as f64).sin()) as f32;
+                [sample, sample]
+            })
+            .collect();
+
+        let mut meter = Meter::new(rate, 2);
+        meter.add_frames(&samples);
+        let loudness = meter.finish();
+        assert_close(integrated(&loudness.blocks).unwrap(), -23.0);
+        assert!((loudness.peak as f64 - amplitude).abs() < 1e-3);
+    }
+}
@@ -1,43 +1,72 @@
+mod album_info;
 mod api;
+mod events;
+mod hooks;
+mod loudness;
+mod search;
+mod serve;
 mod structs;
+mod transcode;
+mod urls;
 mod utils;
+mod watch;
 
 use api::client::IDAGIOClient;
-use api::structs::{AlbumMetaResult, AudioTrack, Author, PersonalPlaylistMetaResult, PlaylistMetaResult, Track};
-use structs::{Args, Config, ParsedAlbumMeta};
+use events::{Event, status};
+use hooks::Hook;
+use api::structs::{AlbumMetaResult, ArtistAlbumsMetaResult, AudioTrack, Author, CollectionAlbum, CollectionArtist, CollectionPlaylist, CollectionRecording, Dash, PersonalPlaylistMetaResult, PlaylistMetaResult, RecordingMetaResult, StreamMetaResult, Track, Video};
+use urls::{APP_URL, MediaType, UrlResolver};
+use structs::{AlbumTrackInfo, ArtistOptions, Args, CollectionKind, Commands, Config, LoudnessTags, ParsedAlbumMeta, SearchArgs, WorkOptions};
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::{self, File};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Write, Error as IoError};
-use std::path::PathBuf;
+use std::panic;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
 
 use clap::Parser;
 use ctr::cipher::{KeyIvInit, StreamCipher};
-use hex;
+use image::ImageError;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
 use indicatif::{ProgressBar, ProgressStyle};
 use metaflac::{Tag as FlacTag, Error as FlacError};
-use metaflac::block::PictureType::CoverFront as FlacCoverFront;
+use metaflac::block::PictureType::{CoverFront as FlacCoverFront, Leaflet as FlacLeaflet};
 use regex::{Regex, Error as RegexError};
 use reqwest::blocking::Response as ReqwestResp;
-use reqwest::Error as ReqwestErr;
-use serde_json;
+use reqwest::{Error as ReqwestErr, StatusCode};
+use reqwest::header::CONTENT_RANGE;
+use serde_json::Value;
 use sha2::{Sha256, Digest};
 use id3::{Error as Id3Error, Tag as Mp3Tag, TagLike, Version};
-use id3::frame::{Picture as Mp3Image, PictureType as Mp3ImageType};
-use mp4ameta::{Tag as Mp4Tag, Data as Mp4Data, Fourcc, Error as Mp4Error};
+use id3::frame::{ExtendedText, Picture as Mp3Image, PictureType as Mp3ImageType};
+use mp4ameta::{Tag as Mp4Tag, Data as Mp4Data, Fourcc, FreeformIdent, Error as Mp4Error};
 
 type Aes128Ctr128BE = ctr::Ctr128BE<aes::Aes128>;
 
 const BUF_SIZE: usize = 1024 * 1024;
 
-const REGEX_STRINGS: [&str; 5] = [
-    r#"^https://app.idagio.com/albums/([a-zA-Z\d-]+)$"#,
-    r#"^https://app.idagio.com/live/event/([a-zA-Z\d-]+)$"#,
-    r#"^https://app.idagio.com/playlists/([a-zA-Z\d-]+)$"#,
-    r#"^https://app.idagio.com/profiles/([a-zA-Z\d-]+)/(?:about|albums)(?:\?([^#]*))?$"#,
-    r#"^https://app.idagio.com/playlists/personal/([0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12})$"#,
- ];
+const TEMP_DIR_NAME: &str = ".temp";
+const SEEN_RECORDINGS_FNAME: &str = ".downloaded_recordings";
+const META_JSON_FNAME: &str = "album.json";
+const PLIST_META_JSON_FNAME: &str = "playlist.json";
+// Added next to the untouched response in album.json, as the response doesn't always have the slug.
+const META_JSON_SLUG_KEY: &str = "idagio_slug";
+
+const COVER_JPEG_QUALITY: u8 = 90;
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PDF_MIME_TYPE: &str = "application/pdf";
+// FLAC metadata blocks have a 24-bit length, with some room left for the picture block's other fields.
+const MAX_EMBED_BOOKLET_SIZE: usize = (1 << 24) - 1024;
+
+const MP4_FREEFORM_MEAN: &str = "com.apple.iTunes";
+const REPLAYGAIN_REF_LUFS: f64 = -18.0;
+const R128_REF_LUFS: f64 = -23.0;
+const ALBUM_ID_TAG: &str = "IDAGIO_ALBUM_ID";
+const TRACK_ID_TAG: &str = "IDAGIO_TRACK_ID";
+const SOURCE_TAG: &str = "IDAGIO_SOURCE";
 
 const SAN_REGEX_STRING: &str = r#"[\/:*?"><|]"#;
 const SECRET: &str = "prod-media-c-YaiJaoni7iebeed5";
@@ -57,7 +86,7 @@ static QUALITY_LIST: [(&str, Quality); 5] = [
     ("aes-128-ctr/mp3-320-", Quality {specs: "320 Kbps MP3", extension: ".mp3", format: &1}),
 ];
 
-fn read_config(exe_path: &PathBuf) -> Result<Config, Box<dyn Error>> {
+fn read_config(exe_path: &Path) -> Result<Config, Box<dyn Error>> {
     let config_path = exe_path.join("config.json");
     let f = File::open(config_path)?;
     let config: Config = serde_json::from_reader(f)?;
@@ -73,11 +102,10 @@ fn resolve_format(fmt: u8) -> Option<u8> {
     }
 }
 
-fn parse_config() -> Result<Config, Box<dyn Error>> {
+fn parse_config(args: Args) -> Result<Config, Box<dyn Error>> {
     let exe_path = utils::get_exe_path()?;
 
     let mut config = read_config(&exe_path)?;
-    let args = Args::parse();
     let proc_urls = utils::process_urls(&args.urls)?;
 
     if args.keep_covers {
@@ -92,6 +120,74 @@ fn parse_config() -> Result<Config, Box<dyn Error>> {
         config.download_booklets = args.download_booklets;
     }
 
+    if args.embed_booklets {
+        config.embed_booklets = args.embed_booklets;
+    }
+
+    if args.booklet_text {
+        config.booklet_text = args.booklet_text;
+    }
+
+    if args.cue_sheet {
+        config.cue_sheet = args.cue_sheet;
+    }
+
+    if args.info_file {
+        config.info_file = args.info_file;
+    }
+
+    if args.meta_json {
+        config.meta_json = args.meta_json;
+    }
+
+    if args.plist_album_tags {
+        config.plist_album_tags = args.plist_album_tags;
+    }
+
+    config.dry_run = args.dry_run;
+    config.all_personal_plists = args.all_personal_playlists;
+
+    config.artist_opts = ArtistOptions {
+        dedup: args.artist_dedup,
+        exclude_compilations: args.artist_exclude_compilations,
+        max: args.artist_max,
+        title_regex: args.artist_title_regex.as_deref().map(Regex::new).transpose()?,
+        year_from: 0,
+        year_to: 0,
+    };
+    if let Some(years) = args.artist_years {
+        let (from, to) = utils::parse_year_range(&years)?;
+        config.artist_opts.year_from = from;
+        config.artist_opts.year_to = to;
+    }
+
+    config.work_opts = WorkOptions {
+        conductor: args.work_conductor,
+        download: args.work_download,
+        ensemble: args.work_ensemble,
+        top: args.work_top,
+        year_from: 0,
+        year_to: 0,
+    };
+    if let Some(years) = args.work_years {
+        let (from, to) = utils::parse_year_range(&years)?;
+        config.work_opts.year_from = from;
+        config.work_opts.year_to = to;
+    }
+
+    if args.replaygain.is_some() {
+        config.replaygain = args.replaygain;
+    }
+
+    if !args.transcode.is_empty() {
+        config.transcode = args.transcode;
+    }
+    config.transcode_profiles = config.transcode.iter()
+        .map(|p| transcode::parse_profile(p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    config.cover_embed_size = args.cover_embed_size.unwrap_or(config.cover_embed_size);
+    config.cover_folder_size = args.cover_folder_size.unwrap_or(config.cover_folder_size);
     config.format = args.format.unwrap_or(config.format);
     config.out_path = args.out_path.unwrap_or(config.out_path);
 
@@ -111,23 +207,6 @@ fn parse_config() -> Result<Config, Box<dyn Error>> {
     Ok(config)
 }
 
-fn check_url(url: &str, regexes: &[Regex]) -> Result<(String, Option<String>, usize), RegexError> {
-    for (idx, re) in regexes.iter().enumerate() {
-        if let Some(capture) = re.captures(url) {
-            if let Some(slug_match) = capture.get(1) {
-                let slug = slug_match.as_str().to_string();
-                if let Some(params_match) = capture.get(2) {
-                    let params = params_match.as_str().to_string();
-                    return Ok((slug, Some(params), idx));
-                }
-                return Ok((slug, None, idx));
-            }
-        }
-    }
-
-    Ok((String::new(), None, 0))
-}
-
 fn derive_key(mut key: Vec<u8>) -> Vec<u8> {
     key.extend_from_slice(SECRET.as_bytes());
 
@@ -136,7 +215,7 @@ fn derive_key(mut key: Vec<u8>) -> Vec<u8> {
     hex_key_base.into_bytes()
 }
 
-fn decrypt(incomp_path: &PathBuf, key: &[u8], iv: &[u8]) -> Result<(), IoError> {
+fn decrypt(incomp_path: &Path, key: &[u8], iv: &[u8]) -> Result<(), IoError> {
     let dec_path = utils::set_path_ext(incomp_path, ".decrypted");
 
     {
@@ -182,34 +261,59 @@ fn sanitise(filename: &str) -> Result<String, RegexError> {
     Ok(re.replace_all(filename, "_").to_string())    
 }
 
-fn download(resp: &mut ReqwestResp, out_path: &PathBuf) -> Result<(), Box<dyn Error>> {
+fn download(resp: &mut ReqwestResp, out_path: &Path) -> Result<(), Box<dyn Error>> {
+    download_from(resp, out_path, 0)
+}
+
+// Appends to out_path when resuming, offset being the number of bytes already on disk.
+fn download_from(resp: &mut ReqwestResp, out_path: &Path, offset: u64) -> Result<(), Box<dyn Error>> {
     let total_size = resp
         .content_length()
         .ok_or("no content length header")?;
 
-    let f = File::create(out_path)?;
+    let f = if offset > 0 {
+        OpenOptions::new().append(true).open(out_path)?
+    } else {
+        File::create(out_path)?
+    };
     let mut writer = BufWriter::new(f);
     let mut buf = vec![0u8; BUF_SIZE];
 
-    let mut downloaded: usize = 0;
-    let pb = ProgressBar::new(total_size);
+    let mut downloaded = offset;
+    let total = total_size + offset;
+    let pb = if events::json_mode() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(total)
+    };
     pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] [{bar:40.cyan/blue}] {percent}% at {binary_bytes_per_sec}, {bytes}/{total_bytes} (ETA: {eta})")?
         .progress_chars("#>-"));
+    pb.set_position(downloaded);
 
+    let mut last_percent = u64::MAX;
     loop {
-        let n = &resp.read(&mut buf)?;
-        if n.to_owned() == 0 {
+        let n = resp.read(&mut buf)?;
+        if n == 0 {
             break;
         }
-        writer.write_all(&buf[..n.to_owned()])?;
-        downloaded += n;
-        pb.set_position(downloaded as u64);
+        writer.write_all(&buf[..n])?;
+        downloaded += n as u64;
+        pb.set_position(downloaded);
+
+        // One event per whole percent is plenty for wrappers.
+        let percent = downloaded * 100 / total.max(1);
+        if percent != last_percent {
+            events::emit(Event::Progress { downloaded, total });
+            last_percent = percent;
+        }
     }
 
+    writer.flush()?;
     pb.finish();
     Ok(())
 }
-fn download_track(c: &mut IDAGIOClient, url: &str, incomp_path: &PathBuf, out_path: &PathBuf) -> Result<(), Box<dyn Error>> {
+
+fn download_track(c: &mut IDAGIOClient, url: &str, incomp_path: &Path, out_path: &Path) -> Result<(), Box<dyn Error>> {
     let mut resp = c.get_file_resp(url, true)?;
     let key_and_iv_str = resp.headers().get("x-x")
         .map_or_else(
@@ -221,7 +325,7 @@ fn download_track(c: &mut IDAGIOClient, url: &str, incomp_path: &PathBuf, out_pa
 
     if !key_and_iv_str.is_empty() {
         let (key, iv) = parse_key_and_iv(&key_and_iv_str)?;
-        println!("Decrypting...");
+        status!("Decrypting...");
         let derived_key = derive_key(key);
         decrypt(incomp_path, &derived_key, &iv)?;
     }
@@ -271,22 +375,46 @@ fn set_vorbis_num(tag: &mut metaflac::Tag, key: &str, n: u16) {
     }
 }
 
-fn write_mp3_tags(track_path: &PathBuf, meta: &ParsedAlbumMeta) -> Result<(), Id3Error> {
+// IDs for matching files back to IDAGIO, plus any others set along the way. Empty ones are left out.
+fn make_custom_tags(meta: &ParsedAlbumMeta) -> Vec<(&'static str, &str)> {
+    let mut tags: Vec<(&'static str, &str)> = vec![
+        (ALBUM_ID_TAG, &meta.album_id),
+        ("IDAGIO_RECORDING_ID", &meta.recording_id),
+        (SOURCE_TAG, meta.kind),
+        (TRACK_ID_TAG, &meta.track_id),
+        ("IDAGIO_URL", &meta.source_url),
+    ];
+    tags.extend(meta.custom_tags.iter().map(|(key, value)| (*key, value.as_str())));
+    tags.retain(|(_, value)| !value.is_empty());
+    tags
+}
+
+fn write_mp3_tags(track_path: &Path, meta: &ParsedAlbumMeta) -> Result<(), Id3Error> {
     let mut tag = Mp3Tag::new();
 
     tag.set_album(&meta.album_title);
     tag.set_album_artist(&meta.album_artist);
     tag.set_artist(&meta.artist);
     tag.set_title(&meta.title);
+    if !meta.grouping.is_empty() {
+        tag.set_text("TIT1", &meta.grouping);
+    }
     tag.set_track(meta.track_num as u32);
     tag.set_total_tracks(meta.track_total as u32);
     if meta.year > 0 {
         tag.set_year(meta.year as i32);
     }
 
+    for (key, value) in make_custom_tags(meta) {
+        tag.add_frame(ExtendedText { description: key.to_string(), value: value.to_string() });
+    }
+    if !meta.isrc.is_empty() {
+        tag.set_text("TSRC", &meta.isrc);
+    }
+
     if !meta.cover_data.is_empty() {
         let pic = Mp3Image {
-            mime_type: "image/jpeg".to_string(),
+            mime_type: cover_mime_type(&meta.cover_data).to_string(),
             picture_type: Mp3ImageType::CoverFront,
             description: String::new(),
             data: meta.cover_data.clone(),
@@ -298,34 +426,52 @@ fn write_mp3_tags(track_path: &PathBuf, meta: &ParsedAlbumMeta) -> Result<(), Id
     Ok(())
 }
 
-fn write_mp4_tags(track_path: &PathBuf, meta: &ParsedAlbumMeta) -> Result<(), Mp4Error> {
-    let mut tag = Mp4Tag::read_from_path(&track_path)?;
+fn write_mp4_tags(track_path: &Path, meta: &ParsedAlbumMeta) -> Result<(), Mp4Error> {
+    let mut tag = Mp4Tag::read_from_path(track_path)?;
 
     tag.set_album(&meta.album_title);
     tag.set_album_artist(&meta.album_artist);
     tag.set_artist(&meta.artist);
     tag.set_title(&meta.title);
+    if !meta.grouping.is_empty() {
+        tag.set_grouping(&meta.grouping);
+    }
     tag.set_track(meta.track_num, meta.track_total);
     if meta.year > 0 {
         tag.set_year(meta.year.to_string());
     }
 
+    for (key, value) in make_custom_tags(meta) {
+        tag.set_data(FreeformIdent::new(MP4_FREEFORM_MEAN, key), Mp4Data::Utf8(value.to_string()));
+    }
+    if !meta.isrc.is_empty() {
+        tag.set_data(FreeformIdent::new(MP4_FREEFORM_MEAN, "ISRC"), Mp4Data::Utf8(meta.isrc.clone()));
+    }
+
+    // Plain binary data in a freeform atom, as MP4 has no attachment type.
+    if !meta.booklet_data.is_empty() {
+        tag.set_data(FreeformIdent::new(MP4_FREEFORM_MEAN, "BOOKLET"), Mp4Data::Reserved(meta.booklet_data.clone()));
+    }
+
     let covr = Fourcc(*b"covr");
-    if !meta.cover_data.is_empty() {
-        tag.add_data(covr, Mp4Data::Jpeg(meta.cover_data.clone()));
+    if is_png(&meta.cover_data) {
+        tag.set_data(covr, Mp4Data::Png(meta.cover_data.clone()));
+    } else if !meta.cover_data.is_empty() {
+        tag.set_data(covr, Mp4Data::Jpeg(meta.cover_data.clone()));
     }
 
-    tag.write_to_path(&track_path)?;
+    tag.write_to_path(track_path)?;
     Ok(())
 }
 
-fn write_flac_tags(track_path: &PathBuf, meta: &ParsedAlbumMeta) -> Result<(), FlacError> {
-    let mut tag = FlacTag::read_from_path(&track_path)?;
+fn write_flac_tags(track_path: &Path, meta: &ParsedAlbumMeta) -> Result<(), FlacError> {
+    let mut tag = FlacTag::read_from_path(track_path)?;
 
     set_vorbis(&mut tag, "ALBUM", &meta.album_title);
     set_vorbis(&mut tag, "ALBUMARTIST", &meta.album_artist);
     set_vorbis(&mut tag, "ARTIST", &meta.artist);
     set_vorbis(&mut tag, "COPYRIGHT", &meta.copyright);
+    set_vorbis(&mut tag, "GROUPING", &meta.grouping);
     set_vorbis(&mut tag, "TITLE", &meta.title);
     set_vorbis(&mut tag, "UPC", &meta.upc);
 
@@ -333,15 +479,28 @@ fn write_flac_tags(track_path: &PathBuf, meta: &ParsedAlbumMeta) -> Result<(), F
     set_vorbis_num(&mut tag, "TRACKTOTAL", meta.track_total);
     set_vorbis_num(&mut tag, "YEAR", meta.year);
 
+    for (key, value) in make_custom_tags(meta) {
+        set_vorbis(&mut tag, key, value);
+    }
+    set_vorbis(&mut tag, "ISRC", &meta.isrc);
+
+    // Replaced rather than added to, so retagging doesn't pile them up.
     if !meta.cover_data.is_empty() {
-        tag.add_picture("image/jpeg", FlacCoverFront, meta.cover_data.clone());
+        tag.remove_picture_type(FlacCoverFront);
+        tag.add_picture(cover_mime_type(&meta.cover_data), FlacCoverFront, meta.cover_data.clone());
+    }
+
+    // FLAC has no attachment block, but picture blocks take any MIME type.
+    if !meta.booklet_data.is_empty() {
+        tag.remove_picture_type(FlacLeaflet);
+        tag.add_picture(PDF_MIME_TYPE, FlacLeaflet, meta.booklet_data.clone());
     }
 
     tag.save()?;
     Ok(())
 }
 
-fn write_tags(track_path: &PathBuf, fmt: &u8, meta: &ParsedAlbumMeta) -> Result<(), Box<dyn Error>> {
+fn write_tags(track_path: &Path, fmt: &u8, meta: &ParsedAlbumMeta) -> Result<(), Box<dyn Error>> {
     match fmt {
         1 => write_mp3_tags(track_path, meta)?,
         2 => write_mp4_tags(track_path, meta)?,
@@ -351,7 +510,228 @@ fn write_tags(track_path: &PathBuf, fmt: &u8, meta: &ParsedAlbumMeta) -> Result<
     Ok(())
 }
 
-fn process_track(c: &mut IDAGIOClient, album_path: &PathBuf, meta: &ParsedAlbumMeta, url: &str) -> Result<(), Box<dyn Error>> {
+fn make_track_fname(track_num: u16, title: &str) -> Result<String, RegexError> {
+    Ok(format!("{:02}. {}", track_num, sanitise(title)?))
+}
+
+// Without the extension. The bool is set when the title had to be dropped to stay within Windows' max path length.
+fn make_track_path_no_ext(album_path: &Path, track_num: u16, title: &str, extension: &str) -> Result<(PathBuf, bool), RegexError> {
+    let track_path_no_ext = album_path.join(make_track_fname(track_num, title)?);
+    let track_path = utils::append_to_path(&track_path_no_ext, extension);
+
+    if cfg!(target_os = "windows") && track_path.to_string_lossy().len() > 255 {
+        let padded_track = format!("{:02}", track_num);
+        return Ok((album_path.join(padded_track), true));
+    }
+    Ok((track_path_no_ext, false))
+}
+
+fn write_track_num_tags(track_path: &Path, fmt: &u8, track_num: u16, track_total: u16) -> Result<(), Box<dyn Error>> {
+    match fmt {
+        1 => {
+            let mut tag = Mp3Tag::read_from_path(track_path)?;
+            tag.set_track(track_num as u32);
+            tag.set_total_tracks(track_total as u32);
+            tag.write_to_path(track_path, Version::Id3v24)?;
+        },
+        2 => {
+            let mut tag = Mp4Tag::read_from_path(track_path)?;
+            tag.set_track(track_num, track_total);
+            tag.write_to_path(track_path)?;
+        },
+        3 => {
+            let mut tag = FlacTag::read_from_path(track_path)?;
+            set_vorbis_num(&mut tag, "TRACKNUMBER", track_num);
+            set_vorbis_num(&mut tag, "TRACKTOTAL", track_total);
+            tag.save()?;
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+// Sets tags on an already tagged file, replacing any earlier values.
+fn write_extra_tags(track_path: &Path, fmt: &u8, tags: &[(&str, String)]) -> Result<(), Box<dyn Error>> {
+    match fmt {
+        1 => {
+            let mut tag = Mp3Tag::read_from_path(track_path)?;
+            for (key, value) in tags {
+                tag.remove_extended_text(Some(key), None);
+                tag.add_frame(ExtendedText { description: key.to_string(), value: value.clone() });
+            }
+            tag.write_to_path(track_path, Version::Id3v24)?;
+        },
+        2 => {
+            let mut tag = Mp4Tag::read_from_path(track_path)?;
+            for (key, value) in tags {
+                tag.set_data(FreeformIdent::new(MP4_FREEFORM_MEAN, key), Mp4Data::Utf8(value.clone()));
+            }
+            tag.write_to_path(track_path)?;
+        },
+        3 => {
+            let mut tag = FlacTag::read_from_path(track_path)?;
+            for (key, value) in tags {
+                set_vorbis(&mut tag, key, value);
+            }
+            tag.save()?;
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+fn make_r128_gain(lufs: f64) -> String {
+    let gain = ((R128_REF_LUFS - lufs) * 256.0).round();
+    (gain.clamp(i16::MIN as f64, i16::MAX as f64) as i16).to_string()
+}
+
+// album is the album's loudness and peak, when the tracks make up one.
+fn make_loudness_tags(kind: LoudnessTags, lufs: f64, peak: f32, album: Option<(f64, f32)>) -> Vec<(&'static str, String)> {
+    let mut tags: Vec<(&'static str, String)> = Vec::new();
+    match kind {
+        LoudnessTags::Replaygain => {
+            tags.push(("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", REPLAYGAIN_REF_LUFS - lufs)));
+            tags.push(("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", peak)));
+            if let Some((album_lufs, album_peak)) = album {
+                tags.push(("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", REPLAYGAIN_REF_LUFS - album_lufs)));
+                tags.push(("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album_peak)));
+            }
+        },
+        LoudnessTags::R128 => {
+            tags.push(("R128_TRACK_GAIN", make_r128_gain(lufs)));
+            if let Some((album_lufs, _)) = album {
+                tags.push(("R128_ALBUM_GAIN", make_r128_gain(album_lufs)));
+            }
+        },
+    }
+    tags
+}
+
+fn process_transcodes(track_paths: &[PathBuf], refresh: bool, config: &Config) {
+    if config.transcode_profiles.is_empty() || config.dry_run {
+        return;
+    }
+    transcode::transcode_tracks(&config.ffmpeg_path, &config.out_path, track_paths, &config.transcode_profiles, refresh);
+}
+
+fn has_loudness_tags(track_path: &Path, kind: LoudnessTags, is_album: bool) -> bool {
+    let fmt = match query_ext_format(track_path) {
+        Some(fmt) => fmt,
+        None => return true,
+    };
+    let keys: &[&str] = match (kind, is_album) {
+        (LoudnessTags::Replaygain, false) => &["REPLAYGAIN_TRACK_GAIN"],
+        (LoudnessTags::Replaygain, true) => &["REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_ALBUM_GAIN"],
+        (LoudnessTags::R128, false) => &["R128_TRACK_GAIN"],
+        (LoudnessTags::R128, true) => &["R128_TRACK_GAIN", "R128_ALBUM_GAIN"],
+    };
+    keys.iter().all(|key| matches!(read_custom_tag(track_path, fmt, key), Ok(Some(_))))
+}
+
+// Runs after tagging. Album gain is only written when the tracks form a whole album or recording.
+fn process_loudness(track_paths: &[PathBuf], is_album: bool, config: &Config) -> Result<(), Box<dyn Error>> {
+    let kind = match config.replaygain {
+        Some(kind) if !config.dry_run => kind,
+        _ => return Ok(()),
+    };
+
+    // Decoding is slow, so reruns over existing tracks skip it. Any new track means album gain has to be redone.
+    if track_paths.iter().all(|track_path| has_loudness_tags(track_path, kind, is_album)) {
+        return Ok(());
+    }
+
+    status!("Analysing loudness...");
+    let mut analysed: Vec<(&PathBuf, &u8, loudness::Loudness)> = Vec::new();
+    for track_path in track_paths {
+        let fmt = match query_ext_format(track_path) {
+            Some(fmt) => fmt,
+            None => continue,
+        };
+        match loudness::analyse(track_path) {
+            Ok(l) => analysed.push((track_path, fmt, l)),
+            Err(e) => events::warn(&format!("Failed to analyse {}.\n{}", track_path.display(), e)),
+        }
+    }
+
+    // Silent or very short tracks have no loudness. They're tagged with a gain of 0 so that they count as done.
+    let ref_lufs = match kind {
+        LoudnessTags::Replaygain => REPLAYGAIN_REF_LUFS,
+        LoudnessTags::R128 => R128_REF_LUFS,
+    };
+
+    let album = if is_album && analysed.len() == track_paths.len() {
+        let blocks: Vec<f64> = analysed.iter().flat_map(|(_, _, l)| l.blocks.iter().copied()).collect();
+        let peak = analysed.iter().map(|(_, _, l)| l.peak).fold(0.0, f32::max);
+        Some((loudness::integrated(&blocks).unwrap_or(ref_lufs), peak))
+    } else {
+        None
+    };
+
+    for (track_path, fmt, l) in &analysed {
+        let lufs = loudness::integrated(&l.blocks).unwrap_or(ref_lufs);
+        let tags = make_loudness_tags(kind, lufs, l.peak, album);
+        write_extra_tags(track_path, fmt, &tags)?;
+    }
+    Ok(())
+}
+
+fn emit_track_done(meta: &ParsedAlbumMeta, track_path: &Path, quality: &'static str, existing: bool) -> Result<(), IoError> {
+    events::emit(Event::TrackDone {
+        track_num: meta.track_num,
+        path: track_path.to_string_lossy().to_string(),
+        quality,
+        bytes: utils::file_size(track_path)?,
+        existing,
+    });
+    Ok(())
+}
+
+fn emit_metadata(kind: &'static str, meta: &ParsedAlbumMeta, path: &Path) {
+    events::emit(Event::Metadata {
+        kind,
+        title: meta.album_title.clone(),
+        artist: meta.album_artist.clone(),
+        path: path.to_string_lossy().to_string(),
+        track_total: meta.track_total,
+    });
+}
+
+fn run_track_hook(meta: &ParsedAlbumMeta, track_path: &Path, quality: &'static str, config: &Config) {
+    hooks::run(&config.on_track_complete, Hook::TrackComplete {
+        path: track_path.to_string_lossy().to_string(),
+        track_num: meta.track_num,
+        track_total: meta.track_total,
+        title: meta.title.clone(),
+        artist: meta.artist.clone(),
+        album_title: meta.album_title.clone(),
+        album_artist: meta.album_artist.clone(),
+        quality,
+        isrc: meta.isrc.clone(),
+        track_id: meta.track_id.clone(),
+        url: meta.source_url.clone(),
+    });
+}
+
+fn run_album_hook(kind: &'static str, meta: &ParsedAlbumMeta, path: &Path, track_paths: &[PathBuf], config: &Config) {
+    if config.dry_run {
+        return;
+    }
+    hooks::run(&config.on_album_complete, Hook::AlbumComplete {
+        kind,
+        path: path.to_string_lossy().to_string(),
+        title: meta.album_title.clone(),
+        artist: meta.album_artist.clone(),
+        track_paths: track_paths.iter().map(|p| p.to_string_lossy().to_string()).collect(),
+        url: meta.source_url.clone(),
+    });
+}
+
+// Returns where the track is (or would be) on disk and its quality.
+fn process_track(c: &mut IDAGIOClient, album_path: &Path, meta: &ParsedAlbumMeta, url: &str, config: &Config) -> Result<(PathBuf, &'static str), Box<dyn Error>> {
+    if serve::cancel_requested() {
+        return Err("cancelled".into());
+    }
+
     let quality = match query_quality(url) {
         Some(q) => q,
         None => {
@@ -360,74 +740,111 @@ fn process_track(c: &mut IDAGIOClient, album_path: &PathBuf, meta: &ParsedAlbumM
         }
     };
 
-    println!("Track {} of {}: {} - {}", meta.track_num, meta.track_total, meta.title, quality.specs);
-
-    let san_track_fname = format!("{:02}. {}", meta.track_num, sanitise(&meta.title)?);
-    let mut track_path_no_ext = album_path.join(san_track_fname);
-    let mut track_path = utils::append_to_path(&track_path_no_ext, quality.extension);
+    status!("Track {} of {}: {} - {}", meta.track_num, meta.track_total, meta.title, quality.specs);
+    events::emit(Event::TrackStart {
+        track_num: meta.track_num,
+        track_total: meta.track_total,
+        title: meta.title.clone(),
+        artist: meta.artist.clone(),
+        quality: quality.specs,
+    });
+
+    let (track_path_no_ext, shortened) = make_track_path_no_ext(album_path, meta.track_num, &meta.title, quality.extension)?;
+    let track_path = utils::append_to_path(&track_path_no_ext, quality.extension);
+    if shortened {
+        events::warn("Track exceeds max path length; will be renamed like <track_num>.<ext> instead.");
+    }
 
-    if cfg!(target_os = "windows") && track_path.to_string_lossy().len() > 255 {
-        let padded_track = format!("{:02}", meta.track_num);
-        track_path_no_ext = album_path.join(padded_track);
-        track_path = utils::append_to_path(&track_path_no_ext, quality.extension);
-        println!("Track exceeds max path length; will be renamed like <track_num>.<ext> instead.");
+    if config.dry_run {
+        status!("Artists: {}", meta.artist);
+        status!("Path: {}", track_path.display());
     }
 
     if utils::file_exists(&track_path)? {
-        println!("Track already exists locally.");
-        return Ok(());
+        status!("Track already exists locally.");
+        emit_track_done(meta, &track_path, quality.specs, true)?;
+        return Ok((track_path, quality.specs));
+    }
+
+    if config.dry_run {
+        return Ok((track_path, quality.specs));
     }
 
     let track_path_incomp = utils::append_to_path(&track_path_no_ext, ".incomplete");
     download_track(c, url, &track_path_incomp, &track_path)?;
     write_tags(&track_path, quality.format, meta)?;
+    emit_track_done(meta, &track_path, quality.specs, false)?;
+    run_track_hook(meta, &track_path, quality.specs, config);
 
-    Ok(())
+    Ok((track_path, quality.specs))
 }
 
 // Merge these three funcs.
 fn parse_album_meta(meta: &AlbumMetaResult, track_total: u16) -> ParsedAlbumMeta {
     ParsedAlbumMeta {
+        album_id: meta.slug.clone(),
         album_title: meta.title.clone(),
         album_artist: meta.participants[0].name.clone(),
-        artist: String::new(),
         copyright: meta.copyright.clone(),
-        cover_data: Vec::new(),
-        title: String::new(),
-        track_num: 0,
+        kind: "album",
+        source_url: format!("{}albums/{}", APP_URL, meta.slug),
         track_total,
         upc: meta.upc.clone(),
         year: meta.copyright_year,
+        ..Default::default()
     }
 }
 
-fn parse_plist_meta(meta: &PlaylistMetaResult, track_total: u16) -> ParsedAlbumMeta {
+fn parse_plist_meta(meta: &PlaylistMetaResult, slug: &str, track_total: u16) -> ParsedAlbumMeta {
     ParsedAlbumMeta {
         album_title: meta.title.clone(),
         album_artist: meta.curator.name.clone(),
-        artist: String::new(),
-        copyright: String::new(),
-        cover_data: Vec::new(),
-        title: String::new(),
-        track_num: 0,
+        kind: "playlist",
+        source_url: format!("{}playlists/{}", APP_URL, slug),
         track_total,
-        upc: String::new(),
-        year: 0,
+        ..Default::default()
     }
 }
 
-fn parse_personal_plist_meta(meta: &PersonalPlaylistMetaResult, track_total: u16) -> ParsedAlbumMeta {
+fn parse_personal_plist_meta(meta: &PersonalPlaylistMetaResult, owner: &str, track_total: u16) -> ParsedAlbumMeta {
     ParsedAlbumMeta {
         album_title: meta.title.clone(),
-        album_artist: meta.user_id.clone(),
-        artist: String::new(),
-        copyright: String::new(),
-        cover_data: Vec::new(),
-        title: String::new(),
-        track_num: 0,
+        album_artist: owner.to_string(),
+        custom_tags: vec![("IDAGIO_PLAYLIST_OWNER_ID", meta.user_id.clone())],
+        kind: "playlist",
+        source_url: format!("{}playlists/personal/{}", APP_URL, meta.id),
+        track_total,
+        ..Default::default()
+    }
+}
+
+fn parse_recording_performers(meta: &RecordingMetaResult) -> String {
+    let mut performers: Vec<String> = Vec::new();
+    if let Some(conductor) = &meta.conductor {
+        performers.push(conductor.name.clone());
+    }
+    performers.extend(meta.ensembles.iter().map(|e| e.name.clone()));
+    performers.extend(meta.soloists.iter().map(|s| s.person.name.clone()));
+    performers.join(", ")
+}
+
+fn parse_recording_year(meta: &RecordingMetaResult) -> u16 {
+    meta.recording_date.as_ref()
+        .and_then(|d| d.from.as_ref())
+        .and_then(|from| from.get(..4))
+        .and_then(|year| year.parse().ok())
+        .unwrap_or(0)
+}
+
+fn parse_recording_meta(meta: &RecordingMetaResult, track_total: u16) -> ParsedAlbumMeta {
+    ParsedAlbumMeta {
+        album_title: meta.work.title.clone(),
+        album_artist: parse_recording_performers(meta),
+        kind: "recording",
+        source_url: format!("{}recordings/{}", APP_URL, meta.id),
         track_total,
-        upc: String::new(),
-        year: 0,
+        year: parse_recording_year(meta),
+        ..Default::default()
     }
 }
 
@@ -438,17 +855,28 @@ fn parse_track_artists(authors: Vec<Author>) -> String {
         .join(", ")
 }
 
-fn parse_track_meta(meta: &mut ParsedAlbumMeta, track_meta: &Track, track_num: u16) {
+fn parse_track_title(track_meta: &Track) -> String {
     let piece_title = &track_meta.piece.title;
 
     let mut title = track_meta.piece.workpart.work.title.clone();
     if title != *piece_title {
         title += &format!(" - {}", piece_title)
     }
+    title
+}
 
+fn parse_track_meta(meta: &mut ParsedAlbumMeta, track_meta: &Track, track_num: u16) {
     meta.artist =  parse_track_artists(track_meta.piece.workpart.work.authors.clone());
-    meta.title = title;
+    meta.title = parse_track_title(track_meta);
     meta.track_num = track_num;
+
+    meta.isrc = track_meta.isrc.clone().unwrap_or_default();
+    meta.recording_id = track_meta.recording.as_ref().map(|r| r.id.clone()).unwrap_or_default();
+    meta.track_id = track_meta.id.clone();
+    // Album tracks don't list their album, so album downloads keep the album's own ID.
+    if meta.kind != "album" {
+        meta.album_id = track_meta.album.as_ref().map(|a| a.slug.clone()).unwrap_or_default();
+    }
 }
 
 fn get_cover_data(c: &mut IDAGIOClient, url: &str) -> Result<Vec<u8>, Box<ReqwestErr>> {
@@ -458,63 +886,247 @@ fn get_cover_data(c: &mut IDAGIOClient, url: &str) -> Result<Vec<u8>, Box<Reqwes
     Ok(body_vec)
 }
 
-fn write_cover(cover_data: &[u8], album_path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let cover_path = album_path.join("folder.jpg");
+// The image service scales covers itself when asked to; 0 leaves the URL as-is.
+fn make_cover_url(url: &str, size: u32) -> String {
+    if size == 0 {
+        return url.to_string();
+    }
+    let sep = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}width={}&height={}&fit=bounds", url, sep, size, size)
+}
+
+fn is_png(cover_data: &[u8]) -> bool {
+    cover_data.starts_with(PNG_MAGIC)
+}
+
+fn cover_mime_type(cover_data: &[u8]) -> &'static str {
+    if is_png(cover_data) {
+        "image/png"
+    } else {
+        "image/jpeg"
+    }
+}
+
+// In case the service ignored the requested size. PNGs are re-encoded too as they're usually huge.
+fn shrink_cover(cover_data: Vec<u8>, max_size: u32) -> Result<Vec<u8>, ImageError> {
+    if max_size == 0 {
+        return Ok(cover_data);
+    }
+
+    let img = image::load_from_memory(&cover_data)?;
+    let too_big = img.width() > max_size || img.height() > max_size;
+    if !too_big && !is_png(&cover_data) {
+        return Ok(cover_data);
+    }
+
+    let img = if too_big {
+        img.resize(max_size, max_size, FilterType::Lanczos3)
+    } else {
+        img
+    };
+
+    let mut shrunk: Vec<u8> = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut shrunk, COVER_JPEG_QUALITY);
+    img.to_rgb8().write_with_encoder(encoder)?;
+    Ok(shrunk)
+}
+
+fn get_embed_cover(c: &mut IDAGIOClient, url: &str, config: &Config) -> Result<Vec<u8>, Box<dyn Error>> {
+    let cover_data = get_cover_data(c, &make_cover_url(url, config.cover_embed_size))?;
+    Ok(shrink_cover(cover_data, config.cover_embed_size)?)
+}
+
+fn write_cover(cover_data: &[u8], album_path: &Path) -> Result<(), Box<dyn Error>> {
+    let cover_fname = if is_png(cover_data) { "folder.png" } else { "folder.jpg" };
+    let cover_path = album_path.join(cover_fname);
     let mut f = File::create(cover_path)?;
     f.write_all(cover_data)?;
     Ok(())
 }
 
-fn download_booklet(c: &mut IDAGIOClient, url: &str, album_path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let booklet_path = album_path.join("booklet.pdf");
+// Fetched once at the larger of the two sizes; the smaller one is scaled down locally from that.
+fn process_cover(c: &mut IDAGIOClient, url: &str, album_path: &Path, meta: &mut ParsedAlbumMeta, config: &Config) -> Result<(), Box<dyn Error>> {
+    let fetch_size = match (config.keep_covers, config.write_covers) {
+        (false, false) => return Ok(()),
+        (true, false) => config.cover_folder_size,
+        (false, true) => config.cover_embed_size,
+        // 0 is as served, i.e. the largest.
+        (true, true) if config.cover_folder_size == 0 || config.cover_embed_size == 0 => 0,
+        (true, true) => config.cover_folder_size.max(config.cover_embed_size),
+    };
+    let cover_data = get_cover_data(c, &make_cover_url(url, fetch_size))?;
+
+    if config.keep_covers {
+        if fetch_size == config.cover_folder_size {
+            write_cover(&cover_data, album_path)?;
+        } else {
+            write_cover(&shrink_cover(cover_data.clone(), config.cover_folder_size)?, album_path)?;
+        }
+    }
+
+    if config.write_covers {
+        meta.cover_data = shrink_cover(cover_data, config.cover_embed_size)?;
+    }
+    Ok(())
+}
+
+// Playlists often have several tracks from the same album, so each cover is only fetched once.
+fn get_cached_cover(c: &mut IDAGIOClient, cover_cache: &mut HashMap<String, Vec<u8>>, url: &str, config: &Config) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some(cover_data) = cover_cache.get(url) {
+        return Ok(cover_data.clone());
+    }
+    let cover_data = get_embed_cover(c, url, config)?;
+    cover_cache.insert(url.to_string(), cover_data.clone());
+    Ok(cover_data)
+}
+
+fn get_booklet_data(c: &mut IDAGIOClient, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
     let mut resp = c.get_file_resp(url, false)?;
-    let mut f = File::create(booklet_path)?;
-    io::copy(&mut resp, &mut f)?;
+    let mut booklet_data: Vec<u8> = Vec::new();
+    resp.read_to_end(&mut booklet_data)?;
+    Ok(booklet_data)
+}
+
+// Fills in {album_artist}, {album_title}, {upc} and {year}. No extension.
+fn make_booklet_fname(template: &str, meta: &ParsedAlbumMeta) -> Result<String, RegexError> {
+    let year = if meta.year > 0 { meta.year.to_string() } else { String::new() };
+    let fname = template
+        .replace("{album_artist}", &meta.album_artist)
+        .replace("{album_title}", &meta.album_title)
+        .replace("{upc}", &meta.upc)
+        .replace("{year}", &year);
+    if fname.trim().is_empty() {
+        return Ok("booklet".to_string());
+    }
+    sanitise(fname.trim())
+}
+
+fn check_embed_booklet(booklet_data: Vec<u8>) -> Vec<u8> {
+    if booklet_data.len() > MAX_EMBED_BOOKLET_SIZE {
+        events::warn("Booklet is too big to embed into tracks; skipped.");
+        return Vec::new();
+    }
+    booklet_data
+}
+
+// pdf-extract panics on some malformed fonts rather than returning an error.
+fn extract_booklet_text(booklet_data: &[u8]) -> Result<String, Box<dyn Error>> {
+    match panic::catch_unwind(|| pdf_extract::extract_text_from_mem(booklet_data)) {
+        Ok(res) => Ok(res?),
+        Err(_) => Err("pdf parser panicked".into()),
+    }
+}
+
+fn write_booklet(booklet_data: &[u8], album_path: &Path, booklet_fname: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    if config.download_booklets {
+        let booklet_path = album_path.join(format!("{}.pdf", booklet_fname));
+        fs::write(booklet_path, booklet_data)?;
+    }
+
+    if config.booklet_text {
+        match extract_booklet_text(booklet_data) {
+            Ok(text) => fs::write(album_path.join(format!("{}.txt", booklet_fname)), text)?,
+            Err(e) => events::warn(&format!("Failed to extract booklet text.\n{}", e)),
+        }
+    }
+    Ok(())
+}
+
+// Unfiltered, so it can be reprocessed offline later.
+fn write_meta_json(raw: &Value, slug: Option<&str>, json_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut sidecar = raw.clone();
+    if let (Some(slug), Value::Object(map)) = (slug, &mut sidecar) {
+        map.insert(META_JSON_SLUG_KEY.to_string(), Value::from(slug));
+    }
+
+    let f = File::create(json_path)?;
+    let mut writer = BufWriter::new(f);
+    serde_json::to_writer_pretty(&mut writer, &sidecar)?;
+    writer.flush()?;
     Ok(())
 }
 
 fn process_album(c: &mut IDAGIOClient, slug: &str, config: &Config) -> Result<(), Box<dyn Error>> {
     let meta = c.get_album_meta(slug)?;
+    download_album(c, meta, config)
+}
 
+fn download_album(c: &mut IDAGIOClient, meta: AlbumMetaResult, config: &Config) -> Result<(), Box<dyn Error>> {
     let track_total = meta.tracks.len() as u16;
     let mut parsed_meta = parse_album_meta(&meta, track_total);
     // meta.tracks.sort_by_key(|t| t.position);
 
     let album_folder = format!("{} - {}", parsed_meta.album_artist, parsed_meta.album_title);
-    println!("{}", album_folder);
+    status!("{}", album_folder);
 
     let san_album_folder = sanitise(&album_folder)?;
-    let album_path = config.out_path.join(san_album_folder);
-    fs::create_dir_all(&album_path)?;
-
-    let stream_meta = c.get_stream_meta(meta.track_ids, config.format)?;
+    let album_path = config.out_path.join(&san_album_folder);
+    emit_metadata("album", &parsed_meta, &album_path);
+    if config.dry_run {
+        status!("Folder: {}", album_path.display());
+    } else {
+        fs::create_dir_all(&album_path)?;
+        if config.meta_json {
+            write_meta_json(&meta.raw, Some(&meta.slug), &album_path.join(META_JSON_FNAME))?;
+        }
+    }
 
-    let cover_data = get_cover_data(c, &meta.image_url)?;
+    let stream_meta = c.get_stream_meta(meta.track_ids.clone(), config.format)?;
 
-    if config.keep_covers {
-        write_cover(&cover_data, &album_path)?;
+    if !config.dry_run {
+        process_cover(c, &meta.image_url, &album_path, &mut parsed_meta, config)?;
     }
 
-    if config.write_covers {
-        parsed_meta.cover_data = cover_data.clone();
+    // Fetched before the tracks so that it can be embedded into them.
+    let wants_booklet = config.download_booklets || config.embed_booklets || config.booklet_text;
+    if let Some(booklet_url) = meta.booklet_url.as_ref().filter(|_| wants_booklet) {
+        let booklet_fname = make_booklet_fname(&config.booklet_template, &parsed_meta)?;
+        if config.dry_run {
+            status!("Booklet available: {}", album_path.join(format!("{}.pdf", booklet_fname)).display());
+        } else {
+            status!("Booklet available; downloading...");
+            let booklet_data = get_booklet_data(c, booklet_url)?;
+            write_booklet(&booklet_data, &album_path, &booklet_fname, config)?;
+            if config.embed_booklets {
+                parsed_meta.booklet_data = check_embed_booklet(booklet_data);
+            }
+        }
     }
 
+    let mut track_infos: Vec<AlbumTrackInfo> = Vec::new();
     for (mut idx, track) in meta.tracks.iter().enumerate() {
         idx += 1;
         if let Some(res) = stream_meta.iter().find(|res| res.id == track.id) {
             parse_track_meta(&mut parsed_meta, track, idx as u16);
-            process_track(c, &album_path, &parsed_meta, &res.url)?;
+            let (track_path, quality) = process_track(c, &album_path, &parsed_meta, &res.url, config)?;
+            track_infos.push(AlbumTrackInfo {
+                artist: parsed_meta.artist.clone(),
+                duration: track.duration,
+                fname: track_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                isrc: parsed_meta.isrc.clone(),
+                quality,
+                title: parsed_meta.title.clone(),
+                track_num: parsed_meta.track_num,
+            });
         } else {
-            println!("The API didn't return any stream metadata for this track.")
+            events::warn("The API didn't return any stream metadata for this track.")
         }
     }
 
-    if config.download_booklets {
-        if let Some(booklet_url) = meta.booklet_url {
-            println!("Booklet available; downloading...");
-            download_booklet(c, &booklet_url, &album_path)?;
+    let track_paths: Vec<PathBuf> = track_infos.iter().map(|t| album_path.join(&t.fname)).collect();
+    process_loudness(&track_paths, true, config)?;
+    process_transcodes(&track_paths, false, config);
+
+    if !config.dry_run {
+        if config.cue_sheet {
+            let cue_path = album_path.join(format!("{}.cue", san_album_folder));
+            album_info::write_cue(&cue_path, &parsed_meta, &track_infos)?;
+        }
+        if config.info_file {
+            album_info::write_info(&album_path, &meta, &parsed_meta, &track_infos)?;
         }
     }
+    run_album_hook("album", &parsed_meta, &album_path, &track_paths, config);
 
     Ok(())
 }
@@ -529,7 +1141,7 @@ fn get_aac_audio(audio: &[AudioTrack]) -> Option<&AudioTrack> {
     audio.iter().find(|&d|d.codecs == "mp4a.40.2")
 }
 
-fn mux_mp4(ffmpeg_path: &PathBuf, video_path: &PathBuf, audio_path: &PathBuf, out_path: &PathBuf) -> Result<(), Box<dyn Error>> {
+fn mux_mp4(ffmpeg_path: &Path, video_path: &Path, audio_path: &Path, out_path: &Path) -> Result<(), Box<dyn Error>> {
     let output: Output = Command::new(ffmpeg_path)
         .arg("-i")
         .arg(video_path)
@@ -549,180 +1161,1193 @@ fn mux_mp4(ffmpeg_path: &PathBuf, video_path: &PathBuf, audio_path: &PathBuf, ou
     Ok(())
 }
 
-fn process_video(c: &mut IDAGIOClient, slug: &str, config: &Config) -> Result<(), Box<dyn Error>> {
-    if !c.user_info.allow_concert_playback {
-        return Err("plan doesn't allow concerts".into());
-    }
-    let meta = c.get_video_meta(slug)?;
-    let name = meta.video.name;
-    println!("{}", name);
+fn remux_manifest(ffmpeg_path: &Path, url: &str, out_path: &Path) -> Result<(), Box<dyn Error>> {
+    let output: Output = Command::new(ffmpeg_path)
+        .arg("-i")
+        .arg(url)
+        .arg("-c")
+        .arg("copy")
+        .arg(out_path)
+        .stderr(Stdio::piped())
+        .output()?;
 
-    if meta.video.source != "vimeo" {
-        return Err("unsupported video source, was expecting vimeo".into());
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let err_msg = format!("bad exit code, output: {}", stderr);
+        Err(err_msg)?
     }
+    Ok(())
+}
 
-    let vimeo_meta = c.get_vimeo_meta(&meta.video.video_id)?;
-    let master_url = vimeo_meta.request.files.dash.cdns.akfire_interconnect_quic.avc_url;
-    let base_url = make_base_url(&master_url)?;
-    let mut master = c.get_video_master(&master_url)?;
-
-    master.audio.sort_by_key(|a| -(a.avg_bitrate as i32));
-
-    master.video.sort_by_key(|v| -(v.height as i16));
-
-    let video = &master.video[0];
+// From a 416's "Content-Range: bytes */<total>".
+fn query_range_total(resp: &ReqwestResp) -> Option<u64> {
+    resp.headers()
+        .get(CONTENT_RANGE)?
+        .to_str().ok()?
+        .strip_prefix("bytes */")?
+        .parse().ok()
+}
 
-    let san_video_name = sanitise(&name)?;
-    let fname_string = format!("{} ({}p).mp4", san_video_name, video.height);
-    let out_path = config.out_path.join(fname_string);
-    if utils::file_exists(&out_path)? {
-        println!("Concert already exists locally.");
+// Completed parts are kept as-is and partial ones are resumed with a range request,
+// so a concert that failed mid-way doesn't have to be fetched from scratch.
+fn download_part(c: &mut IDAGIOClient, url: &str, part_path: &Path) -> Result<(), Box<dyn Error>> {
+    if utils::file_exists(part_path)? {
+        status!("Part already downloaded by an earlier run.");
         return Ok(());
     }
 
-    let audio = get_aac_audio(&master.audio).ok_or("aac audio track not present")?;
-
-    let video_url = format!("{}{}{}.mp4", base_url, video.base_url, video.id);
-    let audio_url = format!("{}{}{}.mp4", base_url, audio.base_url, audio.id);
-
-    let video_path = config.out_path.join("v.mp4");
-    let audio_path = config.out_path.join("a.mp4");
+    let incomp_path = utils::append_to_path(part_path, ".incomplete");
+    let mut offset = utils::file_size(&incomp_path)?;
 
-    println!("Video: ~{} Kbps | {} FPS | {}p ({}x{2})", video.avg_bitrate/1000, video.framerate, video.height, video.width);
-    let mut video_resp = c.get_file_resp(&video_url, true)?;
-    download(&mut video_resp, &video_path)?;
+    let mut resp = if offset > 0 {
+        status!("Resuming from {} bytes...", offset);
+        c.get_file_resp_from(url, offset)?
+    } else {
+        c.get_file_resp(url, true)?
+    };
 
-    println!("Audio: AAC ~{} Kbps", audio.avg_bitrate/1000);
-    let mut audio_resp = c.get_file_resp(&audio_url, true)?;
-    download(&mut audio_resp, &audio_path)?;
+    // A crash between finishing the download and the rename leaves a complete .incomplete.
+    if offset > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        if query_range_total(&resp) == Some(offset) {
+            status!("Part was already fully downloaded by an earlier run.");
+            fs::rename(incomp_path, part_path)?;
+            return Ok(());
+        }
+        events::warn("Leftover part doesn't match the server's; starting over.");
+        resp = c.get_file_resp(url, true)?;
+        offset = 0;
+    }
 
-    println!("Muxing...");
-    mux_mp4(&config.ffmpeg_path, &video_path, &audio_path, &out_path)?;
-    fs::remove_file(video_path)?;
-    fs::remove_file(audio_path)?;
+    if offset > 0 && resp.status() != StatusCode::PARTIAL_CONTENT {
+        events::warn("Server doesn't support resuming; starting over.");
+        offset = 0;
+    }
 
+    download_from(&mut resp, &incomp_path, offset)?;
+    fs::rename(incomp_path, part_path)?;
     Ok(())
 }
 
-fn process_plist(c: &mut IDAGIOClient, slug: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+fn sweep_temp_dir(temp_path: &Path) -> Result<(), IoError> {
+    let entries = match fs::read_dir(temp_path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let mut leftovers: Vec<String> = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            leftovers.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    if leftovers.is_empty() {
+        let _ = fs::remove_dir(temp_path);
+        return Ok(());
+    }
+
+    leftovers.sort();
+    status!("Found {} leftover concert part(s) from an earlier run in {}:", leftovers.len(), temp_path.display());
+    for leftover in leftovers {
+        status!("  {}", leftover);
+    }
+    status!("They'll be resumed when the same concert is downloaded again.\n");
+    Ok(())
+}
+
+enum VideoSource {
+    Vimeo(String),
+    Manifest(String),
+}
+
+fn is_manifest_url(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    path.starts_with("http") && (path.ends_with(".m3u8") || path.ends_with(".mpd"))
+}
+
+fn resolve_video_source(video: &Video) -> Result<VideoSource, Box<dyn Error>> {
+    if video.source == "vimeo" {
+        return Ok(VideoSource::Vimeo(video.video_id.clone()));
+    }
+
+    let candidates = [video.url.as_deref(), Some(video.video_id.as_str())];
+    for url in candidates.into_iter().flatten() {
+        if is_manifest_url(url) {
+            return Ok(VideoSource::Manifest(url.to_string()));
+        }
+    }
+
+    let err_str = format!("unsupported video source: {}", video.source);
+    Err(err_str.into())
+}
+
+// Default CDN first, then the rest in a stable order. CDNs without a usable URL are skipped.
+fn order_cdns(dash: &Dash) -> Vec<(&String, &str)> {
+    let mut cdns: Vec<(&String, &str)> = dash.cdns.iter()
+        .filter_map(|(name, cdn)| {
+            let master_url = [&cdn.avc_url, &cdn.url].into_iter()
+                .flatten()
+                .find(|u| !u.is_empty())?;
+            Some((name, master_url.as_str()))
+        })
+        .collect();
+    cdns.sort_by_key(|(name, _)| (Some(*name) != dash.default_cdn.as_ref(), name.to_string()));
+    cdns
+}
+
+fn process_video(c: &mut IDAGIOClient, slug: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    if !c.user_info.allow_concert_playback {
+        return Err("plan doesn't allow concerts".into());
+    }
+    let meta = c.get_video_meta(slug)?;
+    let name = &meta.video.name;
+    status!("{}", name);
+    events::emit(Event::Metadata {
+        kind: "concert",
+        title: name.clone(),
+        artist: String::new(),
+        path: config.out_path.to_string_lossy().to_string(),
+        track_total: 0,
+    });
+
+    let san_video_name = sanitise(name)?;
+    if config.meta_json && !config.dry_run {
+        write_meta_json(&meta.raw, None, &config.out_path.join(format!("{}.json", san_video_name)))?;
+    }
+    match resolve_video_source(&meta.video)? {
+        VideoSource::Vimeo(video_id) => process_vimeo_video(c, slug, &video_id, &san_video_name, config),
+        VideoSource::Manifest(url) => process_manifest_video(slug, &url, &san_video_name, config),
+    }
+}
+
+fn process_vimeo_video(c: &mut IDAGIOClient, slug: &str, video_id: &str, san_video_name: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let vimeo_meta = c.get_vimeo_meta(video_id)?;
+    let cdns = order_cdns(&vimeo_meta.request.files.dash);
+    let cdn_total = cdns.len();
+
+    let mut last_err: Option<Box<dyn Error>> = None;
+    for (cdn_num, (cdn_name, master_url)) in cdns.into_iter().enumerate() {
+        match download_vimeo_video(c, slug, master_url, san_video_name, config) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                events::warn(&format!("CDN {} failed.\n{}", cdn_name, e));
+                if cdn_num + 1 < cdn_total {
+                    status!("Trying the next CDN...");
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| "vimeo didn't return any cdns".into()))
+}
+
+fn download_vimeo_video(c: &mut IDAGIOClient, slug: &str, master_url: &str, san_video_name: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let base_url = make_base_url(master_url)?;
+    let mut master = c.get_video_master(master_url)?;
+
+    master.audio.sort_by_key(|a| -(a.avg_bitrate as i32));
+
+    master.video.sort_by_key(|v| -(v.height as i16));
+
+    let video = master.video.first().ok_or("master has no video tracks")?;
+
+    let fname_string = format!("{} ({}p).mp4", san_video_name, video.height);
+    let out_path = config.out_path.join(fname_string);
+    if config.dry_run {
+        status!("Path: {}", out_path.display());
+    }
+
+    if utils::file_exists(&out_path)? {
+        status!("Concert already exists locally.");
+        return Ok(());
+    }
+
+    let audio = get_aac_audio(&master.audio).ok_or("aac audio track not present")?;
+
+    if config.dry_run {
+        status!("Video: ~{} Kbps | {} FPS | {}p ({}x{2})", video.avg_bitrate/1000, video.framerate, video.height, video.width);
+        status!("Audio: AAC ~{} Kbps", audio.avg_bitrate/1000);
+        return Ok(());
+    }
+
+    let video_url = format!("{}{}{}.mp4", base_url, video.base_url, video.id);
+    let audio_url = format!("{}{}{}.mp4", base_url, audio.base_url, audio.id);
+
+    let temp_path = config.out_path.join(TEMP_DIR_NAME);
+    fs::create_dir_all(&temp_path)?;
+
+    let part_prefix = sanitise(&format!("{}_{}", slug, video.id))?;
+    let video_path = temp_path.join(format!("{}_v.mp4", part_prefix));
+    let audio_path = temp_path.join(format!("{}_a.mp4", part_prefix));
+    let muxed_path = temp_path.join(format!("{}_muxed.mp4", part_prefix));
+
+    status!("Video: ~{} Kbps | {} FPS | {}p ({}x{2})", video.avg_bitrate/1000, video.framerate, video.height, video.width);
+    download_part(c, &video_url, &video_path)?;
+
+    status!("Audio: AAC ~{} Kbps", audio.avg_bitrate/1000);
+    download_part(c, &audio_url, &audio_path)?;
+
+    status!("Muxing...");
+    if utils::file_exists(&muxed_path)? {
+        fs::remove_file(&muxed_path)?;
+    }
+    mux_mp4(&config.ffmpeg_path, &video_path, &audio_path, &muxed_path)?;
+    fs::rename(muxed_path, out_path)?;
+    fs::remove_file(video_path)?;
+    fs::remove_file(audio_path)?;
+
+    // Only succeeds once no other concert has parts left in there.
+    let _ = fs::remove_dir(&temp_path);
+
+    Ok(())
+}
+
+// FFmpeg handles HLS and DASH manifests itself and picks the best variant.
+fn process_manifest_video(slug: &str, url: &str, san_video_name: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let out_path = config.out_path.join(format!("{}.mp4", san_video_name));
+    if config.dry_run {
+        status!("Path: {}", out_path.display());
+        status!("Manifest: {}", url);
+    }
+
+    if utils::file_exists(&out_path)? {
+        status!("Concert already exists locally.");
+        return Ok(());
+    }
+
+    if config.dry_run {
+        return Ok(());
+    }
+
+    let temp_path = config.out_path.join(TEMP_DIR_NAME);
+    fs::create_dir_all(&temp_path)?;
+    let muxed_path = temp_path.join(format!("{}_muxed.mp4", sanitise(slug)?));
+    if utils::file_exists(&muxed_path)? {
+        fs::remove_file(&muxed_path)?;
+    }
+
+    status!("Downloading and muxing from manifest...");
+    remux_manifest(&config.ffmpeg_path, url, &muxed_path)?;
+    fs::rename(muxed_path, out_path)?;
+
+    let _ = fs::remove_dir(&temp_path);
+
+    Ok(())
+}
+
+// Each source album is only fetched once per playlist; failed lookups are remembered too.
+fn get_source_album<'a>(c: &mut IDAGIOClient, album_cache: &'a mut HashMap<String, Option<AlbumMetaResult>>, track: &Track) -> Option<&'a AlbumMetaResult> {
+    let slug = &track.album.as_ref()?.slug;
+
+    if !album_cache.contains_key(slug) {
+        let album = match c.get_album_meta(slug) {
+            Ok(album) => Some(album),
+            Err(e) => {
+                events::warn(&format!("Failed to get the track's source album; using the playlist's metadata instead.\n{}", e));
+                None
+            },
+        };
+        album_cache.insert(slug.clone(), album);
+    }
+    album_cache.get(slug)?.as_ref()
+}
+
+fn set_source_album_meta(meta: &mut ParsedAlbumMeta, album: &AlbumMetaResult) {
+    meta.album_title = album.title.clone();
+    meta.album_artist = album.participants.first().map(|p| p.name.clone()).unwrap_or_default();
+    meta.copyright = album.copyright.clone();
+    meta.upc = album.upc.clone();
+    meta.year = album.copyright_year;
+}
+
+fn process_plist_tracks(c: &mut IDAGIOClient, plist_path: &Path, tracks: &[Track], stream_meta: &[StreamMetaResult], plist_meta: &ParsedAlbumMeta, config: &Config) -> Result<(), Box<dyn Error>> {
+    let track_covers = config.plist_track_covers && config.write_covers && !config.dry_run;
+    let mut album_cache: HashMap<String, Option<AlbumMetaResult>> = HashMap::new();
+    let mut cover_cache: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut track_paths: Vec<PathBuf> = Vec::new();
+
+    for (mut idx, track) in tracks.iter().enumerate() {
+        idx += 1;
+        let res = match stream_meta.iter().find(|res| res.id == track.id) {
+            Some(res) => res,
+            None => {
+                events::warn("The API didn't return any stream metadata for this track.");
+                continue;
+            },
+        };
+
+        let mut track_meta = plist_meta.clone();
+        if config.plist_album_tags {
+            // The playlist's title moves here since ALBUM now holds the source album's.
+            track_meta.grouping = plist_meta.album_title.clone();
+        }
+
+        if config.plist_album_tags || track_covers {
+            if let Some(album) = get_source_album(c, &mut album_cache, track) {
+                if config.plist_album_tags {
+                    set_source_album_meta(&mut track_meta, album);
+                }
+                if track_covers {
+                    match get_cached_cover(c, &mut cover_cache, &album.image_url, config) {
+                        Ok(cover_data) => track_meta.cover_data = cover_data,
+                        Err(e) => events::warn(&format!("Failed to get the track's album cover; using the playlist's instead.\n{}", e)),
+                    }
+                }
+            }
+        }
+
+        parse_track_meta(&mut track_meta, track, idx as u16);
+        let (track_path, _) = process_track(c, plist_path, &track_meta, &res.url, config)?;
+        track_paths.push(track_path);
+    }
+
+    process_loudness(&track_paths, false, config)?;
+    process_transcodes(&track_paths, false, config);
+    run_album_hook("playlist", plist_meta, plist_path, &track_paths, config);
+    Ok(())
+}
+
+fn process_plist(c: &mut IDAGIOClient, slug: &str, config: &Config) -> Result<(), Box<dyn Error>> {
     let meta = c.get_playlist_meta(slug)?;
     let track_total = meta.tracks.len() as u16;
-    let mut parsed_meta = parse_plist_meta(&meta, track_total);
+    let mut parsed_meta = parse_plist_meta(&meta, slug, track_total);
     // meta.tracks.sort_by_key(|t| t.position);
 
     let plist_folder = format!("{} - {}", meta.curator.name, meta.title);
-    println!("{}", plist_folder);
+    status!("{}", plist_folder);
 
     let san_plist_folder = sanitise(&plist_folder)?;
     let plist_path = config.out_path.join(san_plist_folder);
-    fs::create_dir_all(&plist_path)?;
+    emit_metadata("playlist", &parsed_meta, &plist_path);
+    if config.dry_run {
+        status!("Folder: {}", plist_path.display());
+    } else {
+        fs::create_dir_all(&plist_path)?;
+        if config.meta_json {
+            write_meta_json(&meta.raw, None, &plist_path.join(PLIST_META_JSON_FNAME))?;
+        }
+    }
 
     // The album meta endpoint returns the track IDs as strings, but the plist endpoint returns them as ints instead.
     let ids: Vec<String> = meta.track_ids.iter().map(|id| id.to_string()).collect();
     let stream_meta = c.get_stream_meta(ids, config.format)?;
 
-    for (mut idx, track) in meta.tracks.iter().enumerate() {
-        idx += 1;
-        if let Some(res) = stream_meta.iter().find(|res| res.id == track.id) {
-            parse_track_meta(&mut parsed_meta, track, idx as u16);
-            process_track(c, &plist_path, &parsed_meta, &res.url)?;
-        } else {
-            println!("The API didn't return any stream metadata for this track.")
+    if !config.dry_run {
+        if let Some(image_url) = &meta.image_url {
+            process_cover(c, image_url, &plist_path, &mut parsed_meta, config)?;
         }
     }
+
+    process_plist_tracks(c, &plist_path, &meta.tracks, &stream_meta, &parsed_meta, config)?;
     Ok(())
 }
 
+// Falls back to the raw user ID if the profile has no name set. A failed lookup fails the playlist instead,
+// as falling back then would move it to a different folder than earlier runs used.
+fn resolve_plist_owner(c: &mut IDAGIOClient, user_id: &str) -> Result<String, Box<dyn Error>> {
+    let profile = c.get_user_profile(user_id)
+        .map_err(|e| format!("failed to get the playlist owner's profile: {}", e))?;
+    let owner = profile.display_name
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| user_id.to_string());
+    Ok(owner)
+}
+
 fn process_personal_plist(c: &mut IDAGIOClient, id: &str, config: &Config) -> Result<(), Box<dyn Error>> {
     let meta = c.get_personal_plists_meta(id)?;
+    let owner = resolve_plist_owner(c, &meta.user_id)?;
 
     let track_total = meta.tracks.len() as u16;
-    let mut parsed_meta = parse_personal_plist_meta(&meta, track_total);
+    let mut parsed_meta = parse_personal_plist_meta(&meta, &owner, track_total);
 
-    let plist_folder = format!("{} - {} ({})", meta.user_id, meta.title, meta.id);
-    println!("{}", plist_folder);
+    let plist_folder = format!("{} - {} ({})", owner, meta.title, meta.id);
+    status!("{}", plist_folder);
 
     let san_plist_folder = sanitise(&plist_folder)?;
     let plist_path = config.out_path.join(san_plist_folder);
-    fs::create_dir_all(&plist_path)?;
+    emit_metadata("playlist", &parsed_meta, &plist_path);
+    if config.dry_run {
+        status!("Folder: {}", plist_path.display());
+    } else {
+        fs::create_dir_all(&plist_path)?;
+        if config.meta_json {
+            write_meta_json(&meta.raw, None, &plist_path.join(PLIST_META_JSON_FNAME))?;
+        }
+    }
 
     // The album meta endpoint returns the track IDs as strings, but the plist endpoint returns them as ints instead.
     let ids: Vec<String> = meta.tracks.iter().map(|t| t.id.clone()).collect();
     let stream_meta = c.get_stream_meta(ids, config.format)?;
 
+    // Track number, file name and format of each track we expect to end up with.
+    let mut planned: Vec<(u16, String, &u8)> = Vec::new();
+    for (mut idx, track) in meta.tracks.iter().enumerate() {
+        idx += 1;
+        if let Some(res) = stream_meta.iter().find(|res| res.id == track.id) {
+            if let Some(quality) = query_quality(&res.url) {
+                // Named exactly as process_track will name it, or it'd never be found again.
+                let (track_path_no_ext, _) = make_track_path_no_ext(&plist_path, idx as u16, &parse_track_title(track), quality.extension)?;
+                let fname = track_path_no_ext.file_name().unwrap_or_default().to_string_lossy().to_string() + quality.extension;
+                planned.push((idx as u16, fname, quality.format));
+            }
+        }
+    }
+
+    if !config.dry_run {
+        sync_plist_order(&plist_path, &planned, track_total)?;
+    }
+
+    if !config.dry_run {
+        if let Some(image_url) = &meta.image_url {
+            process_cover(c, image_url, &plist_path, &mut parsed_meta, config)?;
+        }
+    }
+
+    process_plist_tracks(c, &plist_path, &meta.tracks, &stream_meta, &parsed_meta, config)?;
+
+    if !config.dry_run {
+        write_m3u(&plist_path, &planned)?;
+    }
+    Ok(())
+}
+
+// Strips the "01. " prefix so files can be matched up after the playlist's been reordered.
+fn strip_track_num(fname: &str) -> Option<&str> {
+    let (num, rest) = fname.split_once(". ")?;
+    if num.is_empty() || !num.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(rest)
+}
+
+// Renames tracks from an earlier run to their current position and fixes their track numbers,
+// so re-running only downloads what's new.
+fn sync_plist_order(plist_path: &Path, planned: &[(u16, String, &u8)], track_total: u16) -> Result<(), Box<dyn Error>> {
+    let mut existing: Vec<String> = Vec::new();
+    for entry in fs::read_dir(plist_path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            existing.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    let mut moves: Vec<(String, String)> = Vec::new();
+    let mut claimed: HashSet<String> = planned.iter()
+        .map(|(_, fname, _)| fname.clone())
+        .filter(|fname| existing.contains(fname))
+        .collect();
+
+    for (_, fname, _) in planned {
+        if existing.contains(fname) {
+            continue;
+        }
+        let wanted_rest = strip_track_num(fname);
+        let old = existing.iter()
+            .find(|e| !claimed.contains(*e) && strip_track_num(e) == wanted_rest);
+        if let Some(old_fname) = old {
+            claimed.insert(old_fname.clone());
+            moves.push((old_fname.clone(), fname.clone()));
+        }
+    }
+
+    if !moves.is_empty() {
+        status!("Playlist order changed; renaming {} track(s)...", moves.len());
+    }
+
+    // Two passes so that swapping positions can't overwrite a file that hasn't moved yet.
+    for (idx, (old_fname, _)) in moves.iter().enumerate() {
+        let temp_fname = format!(".reorder-{}.tmp", idx);
+        fs::rename(plist_path.join(old_fname), plist_path.join(temp_fname))?;
+    }
+    for (idx, (_, new_fname)) in moves.iter().enumerate() {
+        let temp_fname = format!(".reorder-{}.tmp", idx);
+        fs::rename(plist_path.join(temp_fname), plist_path.join(new_fname))?;
+    }
+
+    for (track_num, fname, fmt) in planned {
+        let track_path = plist_path.join(fname);
+        if utils::file_exists(&track_path)? {
+            write_track_num_tags(&track_path, fmt, *track_num, track_total)?;
+        }
+    }
+
+    let stale = existing.iter()
+        .filter(|e| !claimed.contains(*e) && strip_track_num(e).is_some())
+        .count();
+    if stale > 0 {
+        events::warn(&format!("{} track(s) in the folder are no longer in the playlist.", stale));
+    }
+    Ok(())
+}
+
+fn write_m3u(plist_path: &Path, planned: &[(u16, String, &u8)]) -> Result<(), Box<dyn Error>> {
+    let f = File::create(plist_path.join("playlist.m3u8"))?;
+    let mut writer = BufWriter::new(f);
+    writeln!(writer, "#EXTM3U")?;
+    for (_, fname, _) in planned {
+        if utils::file_exists(&plist_path.join(fname))? {
+            writeln!(writer, "{}", fname)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn process_recording(c: &mut IDAGIOClient, id: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let meta = c.get_recording_meta(id)?;
+
+    let track_total = meta.tracks.len() as u16;
+    let mut parsed_meta = parse_recording_meta(&meta, track_total);
+
+    let composers = parse_track_artists(meta.work.authors.clone());
+    let mut rec_folder = format!("{} - {} ({})", composers, meta.work.title, parsed_meta.album_artist);
+    if parsed_meta.year > 0 {
+        rec_folder = format!("{} [{}]", rec_folder, parsed_meta.year);
+    }
+    status!("{}", rec_folder);
+
+    let san_rec_folder = sanitise(&rec_folder)?;
+    let rec_path = config.out_path.join(san_rec_folder);
+    emit_metadata("recording", &parsed_meta, &rec_path);
+    if config.dry_run {
+        status!("Folder: {}", rec_path.display());
+    } else {
+        fs::create_dir_all(&rec_path)?;
+    }
+
+    let ids: Vec<String> = meta.tracks.iter().map(|t| t.id.clone()).collect();
+    let stream_meta = c.get_stream_meta(ids, config.format)?;
+
+    let mut track_paths: Vec<PathBuf> = Vec::new();
     for (mut idx, track) in meta.tracks.iter().enumerate() {
         idx += 1;
         if let Some(res) = stream_meta.iter().find(|res| res.id == track.id) {
             parse_track_meta(&mut parsed_meta, track, idx as u16);
-            process_track(c, &plist_path, &parsed_meta, &res.url)?;
+            parsed_meta.recording_id = meta.id.clone();
+            let (track_path, _) = process_track(c, &rec_path, &parsed_meta, &res.url, config)?;
+            track_paths.push(track_path);
         } else {
-            println!("The API didn't return any stream metadata for this track.")
+            events::warn("The API didn't return any stream metadata for this track.")
+        }
+    }
+
+    process_loudness(&track_paths, true, config)?;
+    process_transcodes(&track_paths, false, config);
+    run_album_hook("recording", &parsed_meta, &rec_path, &track_paths, config);
+    Ok(())
+}
+
+fn matches_name(names: &[String], filter: &Option<String>) -> bool {
+    match filter {
+        Some(f) => {
+            let f = f.to_lowercase();
+            names.iter().any(|n| n.to_lowercase().contains(&f))
+        },
+        None => true,
+    }
+}
+
+fn work_recording_wanted(meta: &RecordingMetaResult, config: &Config) -> bool {
+    let opts = &config.work_opts;
+
+    let conductors: Vec<String> = meta.conductor.iter().map(|c| c.name.clone()).collect();
+    let ensembles: Vec<String> = meta.ensembles.iter().map(|e| e.name.clone()).collect();
+    if !matches_name(&conductors, &opts.conductor) || !matches_name(&ensembles, &opts.ensemble) {
+        return false;
+    }
+
+    if opts.year_from > 0 {
+        let year = parse_recording_year(meta);
+        return year >= opts.year_from && year <= opts.year_to;
+    }
+    true
+}
+
+fn process_work(c: &mut IDAGIOClient, id: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let top = config.work_opts.top;
+    let mut recordings: Vec<RecordingMetaResult> = Vec::new();
+    let mut cursor_opt: Option<String> = None;
+
+    loop {
+        let page = c.get_work_recordings_meta(id, cursor_opt.as_deref())?;
+        recordings.extend(page.results.into_iter().filter(|r| work_recording_wanted(r, config)));
+        if recordings.len() >= top {
+            break;
+        }
+        match page.meta.cursor.next {
+            Some(next) => cursor_opt = Some(next),
+            None => break,
+        }
+    }
+    recordings.truncate(top);
+
+    if recordings.is_empty() {
+        return Err("no recordings of this work matched the filters".into());
+    }
+
+    let work = &recordings[0].work;
+    status!("{} - {}", parse_track_artists(work.authors.clone()), work.title);
+    let rec_total = recordings.len();
+    for (mut rec_num, rec) in recordings.iter().enumerate() {
+        rec_num += 1;
+        let year = parse_recording_year(rec);
+        let year_str = if year > 0 { year.to_string() } else { "year unknown".to_string() };
+        status!("{:>3}. {} ({})", rec_num, parse_recording_performers(rec), year_str);
+        status!("     {}recordings/{}", APP_URL, rec.id);
+    }
+
+    if !config.work_opts.download {
+        status!("Pass --work-download to download these recordings.");
+        return Ok(());
+    }
+
+    for (mut rec_num, rec) in recordings.iter().enumerate() {
+        rec_num += 1;
+        if serve::cancel_requested() {
+            return Err("cancelled".into());
+        }
+        status!("Recording {} of {}:", rec_num, rec_total);
+        if let Err(e) = process_recording(c, &rec.id, config) {
+            status!("Recording failed.\n{}", e);
+            events::emit(Event::Error { source: rec.id.clone(), message: e.to_string() });
+        }
+    }
+    Ok(())
+}
+
+fn artist_album_wanted(meta: &ArtistAlbumsMetaResult, config: &Config) -> bool {
+    let opts = &config.artist_opts;
+
+    if opts.exclude_compilations && meta.is_compilation {
+        return false;
+    }
+
+    if let Some(re) = &opts.title_regex {
+        if !re.is_match(&meta.title) {
+            return false;
         }
     }
+
+    if opts.year_from > 0 {
+        return meta.copyright_year >= opts.year_from && meta.copyright_year <= opts.year_to;
+    }
+    true
+}
+
+fn get_recording_ids(meta: &AlbumMetaResult) -> Vec<String> {
+    meta.tracks.iter()
+        .filter_map(|t| t.recording.as_ref().map(|r| r.id.clone()))
+        .collect()
+}
+
+fn read_seen_recordings(path: &Path) -> Result<HashSet<String>, IoError> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn append_seen_recordings(path: &Path, recording_ids: &[String]) -> Result<(), IoError> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    for id in recording_ids {
+        writeln!(f, "{}", id)?;
+    }
     Ok(())
 }
 
 fn process_artist(c: &mut IDAGIOClient, slug: &str, params: Option<String>, config: &Config) -> Result<(), Box<dyn Error>> {
-    let meta = c.get_artist_albums_meta(slug, params)?;
+    let mut meta = c.get_artist_albums_meta(slug, params)?;
+
+    let unfiltered_total = meta.len();
+    meta.retain(|album_meta| artist_album_wanted(album_meta, config));
+    if meta.len() < unfiltered_total {
+        status!("{} of {} albums left after filtering.", meta.len(), unfiltered_total);
+    }
 
+    // Kept in the output folder, so that reruns also skip duplicates of albums from earlier runs.
+    let seen_path = config.out_path.join(SEEN_RECORDINGS_FNAME);
+    let mut seen_recording_ids: HashSet<String> = if config.artist_opts.dedup {
+        read_seen_recordings(&seen_path)?
+    } else {
+        HashSet::new()
+    };
+
+    // The cap counts albums actually downloaded, so skipped and failed ones don't use it up.
+    let mut downloaded = 0;
     let album_total = meta.len();
     for (mut album_num, album_meta) in meta.iter().enumerate() {
         album_num += 1;
-        println!("Album {} of {}:", album_num, album_total);
-        if let Err(e) = process_album(c, &album_meta.slug, config) {
-            println!("Album failed.\n{}", e);
+        if serve::cancel_requested() {
+            return Err("cancelled".into());
+        }
+        if let Some(max) = config.artist_opts.max.filter(|max| downloaded >= *max) {
+            status!("Reached the max of {} albums.", max);
+            break;
+        }
+        status!("Album {} of {}:", album_num, album_total);
+
+        let res = c.get_album_meta(&album_meta.slug)
+            .and_then(|album| {
+                let recording_ids = get_recording_ids(&album);
+                let all_seen = !recording_ids.is_empty() && recording_ids.iter().all(|id| seen_recording_ids.contains(id));
+                if config.artist_opts.dedup && all_seen {
+                    status!("{}\nAll of this album's recordings were already downloaded; skipping.", album.title);
+                    return Ok(false);
+                }
+                download_album(c, album, config)?;
+                if config.artist_opts.dedup && !config.dry_run {
+                    append_seen_recordings(&seen_path, &recording_ids)?;
+                }
+                seen_recording_ids.extend(recording_ids);
+                Ok(true)
+            });
+
+        match res {
+            Ok(true) => downloaded += 1,
+            Ok(false) => {},
+            Err(e) => {
+                status!("Album failed.\n{}", e);
+                events::emit(Event::Error { source: album_meta.slug.clone(), message: e.to_string() });
+            },
         }
     }
 
     Ok(())
 }
 
-fn compile_regexes() -> Result<Vec<Regex>, regex::Error> {
-    REGEX_STRINGS.iter()
-        .map(|&s| Regex::new(s))
-        .collect()
+fn parse_collection_tracks_meta(track_total: u16) -> ParsedAlbumMeta {
+    ParsedAlbumMeta {
+        album_title: "Favourite tracks".to_string(),
+        album_artist: "Collection".to_string(),
+        kind: "collection",
+        track_total,
+        ..Default::default()
+    }
+}
+
+fn process_collection_tracks(c: &mut IDAGIOClient, config: &Config) -> Result<(), Box<dyn Error>> {
+    let tracks: Vec<Track> = c.get_collection("tracks")?;
+
+    let track_total = tracks.len() as u16;
+    let mut parsed_meta = parse_collection_tracks_meta(track_total);
+
+    let tracks_folder = format!("{} - {}", parsed_meta.album_artist, parsed_meta.album_title);
+    status!("{}", tracks_folder);
+
+    let tracks_path = config.out_path.join(sanitise(&tracks_folder)?);
+    emit_metadata("collection", &parsed_meta, &tracks_path);
+    if config.dry_run {
+        status!("Folder: {}", tracks_path.display());
+    } else {
+        fs::create_dir_all(&tracks_path)?;
+    }
+
+    let ids: Vec<String> = tracks.iter().map(|t| t.id.clone()).collect();
+    let stream_meta = c.get_stream_meta(ids, config.format)?;
+
+    let mut track_paths: Vec<PathBuf> = Vec::new();
+    for (mut idx, track) in tracks.iter().enumerate() {
+        idx += 1;
+        if let Some(res) = stream_meta.iter().find(|res| res.id == track.id) {
+            parse_track_meta(&mut parsed_meta, track, idx as u16);
+            let (track_path, _) = process_track(c, &tracks_path, &parsed_meta, &res.url, config)?;
+            track_paths.push(track_path);
+        } else {
+            events::warn("The API didn't return any stream metadata for this track.")
+        }
+    }
+
+    process_loudness(&track_paths, false, config)?;
+    process_transcodes(&track_paths, false, config);
+    run_album_hook("collection", &parsed_meta, &tracks_path, &track_paths, config);
+    Ok(())
+}
+
+// Each favourite is processed like a URL of its type, so failures don't stop the rest.
+fn process_collection_items<F>(c: &mut IDAGIOClient, kind_name: &str, ids: Vec<String>, mut process: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&mut IDAGIOClient, &str) -> Result<(), Box<dyn Error>>,
+{
+    let item_total = ids.len();
+    status!("Favourite {}: {}", kind_name, item_total);
+    for (mut item_num, id) in ids.iter().enumerate() {
+        item_num += 1;
+        if serve::cancel_requested() {
+            return Err("cancelled".into());
+        }
+        status!("{} of {}:", item_num, item_total);
+        if let Err(e) = process(c, id) {
+            status!("Failed.\n{}", e);
+            events::emit(Event::Error { source: id.clone(), message: e.to_string() });
+        }
+    }
+    Ok(())
+}
+
+fn process_collection_kind(c: &mut IDAGIOClient, kind: CollectionKind, config: &Config) -> Result<(), Box<dyn Error>> {
+    match kind {
+        CollectionKind::Albums => {
+            let albums: Vec<CollectionAlbum> = c.get_collection("albums")?;
+            let slugs = albums.into_iter().map(|a| a.slug).collect();
+            process_collection_items(c, "albums", slugs, |c, slug| process_album(c, slug, config))
+        },
+        CollectionKind::Recordings => {
+            let recordings: Vec<CollectionRecording> = c.get_collection("recordings")?;
+            let ids = recordings.into_iter().map(|r| r.id).collect();
+            process_collection_items(c, "recordings", ids, |c, id| process_recording(c, id, config))
+        },
+        CollectionKind::Tracks => process_collection_tracks(c, config),
+        CollectionKind::Playlists => {
+            let plists: Vec<CollectionPlaylist> = c.get_collection("playlists")?;
+            let slugs = plists.into_iter().map(|p| p.slug).collect();
+            process_collection_items(c, "playlists", slugs, |c, slug| process_plist(c, slug, config))
+        },
+        CollectionKind::Artists => {
+            let artists: Vec<CollectionArtist> = c.get_collection("artists")?;
+            let slugs = artists.into_iter().map(|a| a.slug).collect();
+            process_collection_items(c, "artists", slugs, |c, slug| process_artist(c, slug, None, config))
+        },
+    }
+}
+
+fn process_collection(c: &mut IDAGIOClient, kinds: &[CollectionKind], config: &Config) {
+    let all_kinds = [
+        CollectionKind::Albums,
+        CollectionKind::Recordings,
+        CollectionKind::Tracks,
+        CollectionKind::Playlists,
+        CollectionKind::Artists,
+    ];
+    let kinds = if kinds.is_empty() { &all_kinds[..] } else { kinds };
+
+    for kind in kinds {
+        if let Err(e) = process_collection_kind(c, *kind, config) {
+            status!("Collection failed.\n{}", e);
+            events::emit(Event::Error { source: "collection".to_string(), message: e.to_string() });
+        }
+    }
+}
+
+fn query_ext_format(track_path: &Path) -> Option<&'static u8> {
+    let ext = format!(".{}", track_path.extension()?.to_string_lossy().to_lowercase());
+    QUALITY_LIST.iter()
+        .find(|(_, quality)| quality.extension == ext)
+        .map(|(_, quality)| quality.format)
+}
+
+fn read_custom_tag(track_path: &Path, fmt: &u8, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let value = match fmt {
+        1 => {
+            let tag = Mp3Tag::read_from_path(track_path)?;
+            let value = tag.extended_texts()
+                .find(|t| t.description == key)
+                .map(|t| t.value.clone());
+            value
+        },
+        2 => {
+            let tag = Mp4Tag::read_from_path(track_path)?;
+            let value = tag.strings_of(&FreeformIdent::new(MP4_FREEFORM_MEAN, key))
+                .next()
+                .map(|v| v.to_string());
+            value
+        },
+        3 => {
+            let tag = FlacTag::read_from_path(track_path)?;
+            let value = tag.get_vorbis(key)
+                .and_then(|mut v| v.next())
+                .map(|v| v.to_string());
+            value
+        },
+        _ => None,
+    };
+    Ok(value.filter(|v| !v.is_empty()))
+}
+
+fn read_meta_json_album_id(json_path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    let f = match File::open(json_path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let raw: Value = serde_json::from_reader(BufReader::new(f))?;
+    let slug = [&raw[META_JSON_SLUG_KEY], &raw["result"]["slug"]].into_iter()
+        .filter_map(|v| v.as_str())
+        .find(|slug| !slug.is_empty())
+        .map(|slug| slug.to_string());
+    Ok(slug)
+}
+
+fn list_audio_files(album_path: &Path) -> Result<Vec<(PathBuf, &'static u8)>, IoError> {
+    let mut tracks: Vec<(PathBuf, &u8)> = Vec::new();
+    for entry in fs::read_dir(album_path)? {
+        let track_path = entry?.path();
+        if let Some(fmt) = query_ext_format(&track_path) {
+            tracks.push((track_path, fmt));
+        }
+    }
+    tracks.sort();
+    Ok(tracks)
+}
+
+// From album.json when there is one, or else the first file's tags. Files from playlist, recording and
+// collection downloads carry their own source album's ID, so only album downloads are taken.
+fn find_album_id(album_path: &Path, tracks: &[(PathBuf, &u8)]) -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(album_id) = read_meta_json_album_id(&album_path.join(META_JSON_FNAME))? {
+        return Ok(Some(album_id));
+    }
+
+    let (track_path, fmt) = match tracks.first() {
+        Some(track) => track,
+        None => return Ok(None),
+    };
+    if read_custom_tag(track_path, fmt, SOURCE_TAG)?.as_deref() != Some("album") {
+        return Ok(None);
+    }
+    read_custom_tag(track_path, fmt, ALBUM_ID_TAG)
+}
+
+// Files are matched to the album's tracks by their track ID tag, so renamed or reordered files
+// still get the right tags. Folders with files from other albums are left alone. False if it's not an album folder.
+fn retag_album(c: &mut IDAGIOClient, album_path: &Path, tracks: &[(PathBuf, &u8)], config: &Config) -> Result<bool, Box<dyn Error>> {
+    let album_id = match find_album_id(album_path, tracks)? {
+        Some(album_id) => album_id,
+        None => return Ok(false),
+    };
+
+    let mut track_ids: Vec<Option<String>> = Vec::new();
+    for (track_path, fmt) in tracks {
+        let file_album_id = read_custom_tag(track_path, fmt, ALBUM_ID_TAG)?;
+        if file_album_id.as_deref() != Some(album_id.as_str()) {
+            let err_str = format!("{} isn't tagged as part of album {}; folder skipped", track_path.display(), album_id);
+            return Err(err_str.into());
+        }
+        track_ids.push(read_custom_tag(track_path, fmt, TRACK_ID_TAG)?);
+    }
+
+    let meta = c.get_album_meta(&album_id)?;
+    let track_total = meta.tracks.len() as u16;
+    let mut parsed_meta = parse_album_meta(&meta, track_total);
+    status!("{} - {}", parsed_meta.album_artist, parsed_meta.album_title);
+    emit_metadata("album", &parsed_meta, album_path);
+
+    let mut matched: Vec<(u16, &Track, &PathBuf, &u8)> = Vec::new();
+    for ((track_path, fmt), track_id) in tracks.iter().zip(&track_ids) {
+        let found = track_id.as_ref().and_then(|id| meta.tracks.iter().position(|t| &t.id == id));
+        match found {
+            Some(idx) => matched.push(((idx + 1) as u16, &meta.tracks[idx], track_path, fmt)),
+            None => events::warn(&format!("{} has no matching track on the album anymore; skipped.", track_path.display())),
+        }
+    }
+
+    if config.dry_run {
+        for (track_num, _, track_path, _) in &matched {
+            status!("Track {}: {}", track_num, track_path.display());
+        }
+        return Ok(true);
+    }
+
+    if config.write_covers {
+        parsed_meta.cover_data = get_embed_cover(c, &meta.image_url, config)?;
+    }
+
+    if config.embed_booklets {
+        if let Some(booklet_url) = &meta.booklet_url {
+            parsed_meta.booklet_data = check_embed_booklet(get_booklet_data(c, booklet_url)?);
+        }
+    }
+
+    for (track_num, track, track_path, fmt) in &matched {
+        parse_track_meta(&mut parsed_meta, track, *track_num);
+        status!("Track {} of {}: {}", track_num, track_total, parsed_meta.title);
+        write_tags(track_path, fmt, &parsed_meta)?;
+    }
+
+    // MP3 tags are written from scratch, so gain tags have to be redone.
+    let track_paths: Vec<PathBuf> = matched.iter().map(|(_, _, track_path, _)| track_path.to_path_buf()).collect();
+    process_loudness(&track_paths, true, config)?;
+    // The copies carry the old tags otherwise.
+    process_transcodes(&track_paths, true, config);
+    Ok(true)
+}
+
+// Only tags are rewritten; the audio itself is left alone.
+fn process_retag(c: &mut IDAGIOClient, path: &Path, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut album_paths: Vec<PathBuf> = vec![path.to_path_buf()];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            album_paths.push(entry.path());
+        }
+    }
+    album_paths[1..].sort();
+
+    let mut retagged = 0;
+    for album_path in album_paths {
+        let tracks = list_audio_files(&album_path)?;
+        if tracks.is_empty() {
+            continue;
+        }
+        if serve::cancel_requested() {
+            return Err("cancelled".into());
+        }
+
+        status!("{}", album_path.display());
+        match retag_album(c, &album_path, &tracks, config) {
+            Ok(true) => retagged += 1,
+            Ok(false) => status!("Not an album folder; skipped."),
+            Err(e) => {
+                status!("Retagging failed.\n{}", e);
+                events::emit(Event::Error { source: album_path.to_string_lossy().to_string(), message: e.to_string() });
+            },
+        }
+    }
+
+    if retagged == 0 {
+        return Err("no albums were retagged".into());
+    }
+    status!("Retagged {} album(s).", retagged);
+    Ok(())
+}
+
+fn process_url(c: &mut IDAGIOClient, url: &str, resolver: &UrlResolver, config: &Config) -> Result<(), Box<dyn Error>> {
+    let resolved = match resolver.resolve(url) {
+        Ok(r) => r,
+        Err(e) => {
+            let err_str = format!("Invalid URL: {} ({})", url, e);
+            return Err(err_str.into());
+        }
+    };
+
+    let slug = &resolved.id;
+    match resolved.media_type {
+        MediaType::Album => process_album(c, slug, config),
+        MediaType::Concert => process_video(c, slug, config),
+        MediaType::Playlist => process_plist(c, slug, config),
+        MediaType::Artist => process_artist(c, slug, resolved.params, config),
+        MediaType::PersonalPlaylist => process_personal_plist(c, slug, config),
+        MediaType::Recording => process_recording(c, slug, config),
+        MediaType::Work => process_work(c, slug, config),
+        media_type => {
+            let err_str = format!("{} URLs aren't supported for downloading yet", media_type);
+            Err(err_str.into())
+        },
+    }
+}
+
+// Runs the url_failed hook for all failures, including those from serve and watch mode.
+fn process_url_with_hooks(c: &mut IDAGIOClient, url: &str, resolver: &UrlResolver, config: &Config) -> Result<(), Box<dyn Error>> {
+    let res = process_url(c, url, resolver, config);
+    if config.dry_run {
+        return res;
+    }
+    if let Err(e) = &res {
+        hooks::run(&config.on_url_failed, Hook::UrlFailed { url: url.to_string(), error: e.to_string() });
+    }
+    res
+}
+
+fn process_urls(c: &mut IDAGIOClient, urls: &[String], resolver: &UrlResolver, config: &Config) {
+    let url_total = urls.len();
+
+    for (mut url_num, url) in urls.iter().enumerate() {
+        url_num += 1;
+        status!("URL {} of {}:", url_num, url_total);
+        events::emit(Event::UrlStart { url: url.clone(), url_num, url_total });
+        if let Err(e) = process_url_with_hooks(c, url, resolver, config) {
+            status!("URL failed.\n{}", e);
+            events::emit(Event::Error { source: url.clone(), message: e.to_string() });
+            continue;
+        }
+        events::emit(Event::UrlDone { url: url.clone() });
+    }
+}
+
+fn run_search(c: &mut IDAGIOClient, search_args: &SearchArgs, resolver: &UrlResolver, config: &Config) -> Result<(), Box<dyn Error>> {
+    let res = c.search(&search_args.query, search_args.limit)?;
+    let items = search::flatten_results(res, search_args.r#type);
+
+    if events::json_mode() {
+        events::emit(Event::SearchResults { query: search_args.query.clone(), items: items.clone() });
+    } else if search_args.json {
+        search::print_results_json(&items)?;
+    } else {
+        search::print_results(&items);
+    }
+
+    if search_args.download.is_empty() {
+        return Ok(());
+    }
+
+    let mut urls: Vec<String> = Vec::new();
+    for idx in &search_args.download {
+        match items.iter().find(|item| item.index == *idx) {
+            Some(item) => urls.push(item.url.clone()),
+            None => events::warn(&format!("No search result with index {}.", idx)),
+        }
+    }
+
+    status!();
+    process_urls(c, &urls, resolver, config);
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let config = parse_config()
+    let mut args = Args::parse();
+    let mut command = args.command.take();
+    if let Some(Commands::Info(info_args)) = command {
+        args.dry_run = true;
+        args.urls = info_args.urls;
+        command = None;
+    }
+    events::set_json_mode(args.json);
+    let mut config = parse_config(args)
         .expect("failed to parse args/config");
-    fs::create_dir_all(&config.out_path)?;
-    
+    if !config.dry_run {
+        fs::create_dir_all(&config.out_path)?;
+        sweep_temp_dir(&config.out_path.join(TEMP_DIR_NAME))?;
+    }
+
     let mut c = IDAGIOClient::new()?;
     c.auth(&config.email, &config.password)
         .expect("failed to auth");
     
-    println!("Signed in successfully - {}\n", c.user_info.plan_display_name);
+    status!("Signed in successfully - {}\n", c.user_info.plan_display_name);
+    events::emit(Event::SignedIn { plan: c.user_info.plan_display_name.clone(), premium: c.user_info.premium });
 
     if !c.user_info.premium {
-        println!("No active subscription; audio quality limited.");
+        status!("No active subscription; audio quality limited.");
     }
 
-    let regexes = compile_regexes()?;
-    let url_total = config.urls.len();
+    let resolver = UrlResolver::new()?;
 
-    for (mut url_num, url) in config.urls.iter().enumerate() {
-        url_num += 1;
-        println!("URL {} of {}:", url_num, url_total);
-        let (slug, params, media_type) = check_url(&url, &regexes)?;
-        if slug.is_empty() {
-            println!("Invalid URL: {}", url);
-            continue;
+    if config.all_personal_plists {
+        let plists = c.get_personal_plists()?;
+        status!("Found {} personal playlist(s).", plists.len());
+        for plist in plists {
+            status!("  {}", plist.title);
+            let url = format!("{}playlists/personal/{}", APP_URL, plist.id);
+            if !config.urls.contains(&url) {
+                config.urls.push(url);
+            }
         }
+    }
 
-        let res = match media_type {
-            0 => process_album(&mut c, &slug, &config),
-            1 => process_video(&mut c, &slug, &config),
-            2 => process_plist(&mut c, &slug, &config),
-            3 => process_artist(&mut c, &slug, params, &config),
-            4 => process_personal_plist(&mut c, &slug, &config),
-            _ => Ok(()),
-        };
-
-        if let Err(e) = res {
-            println!("URL failed.\n{}", e);
-        }
+    match command {
+        Some(Commands::Search(search_args)) => run_search(&mut c, &search_args, &resolver, &config)?,
+        Some(Commands::Serve(serve_args)) => {
+            serve::run(&mut c, &config.out_path, &serve_args.bind, serve_args.token, |c, url| process_url_with_hooks(c, url, &resolver, &config))?;
+        },
+        Some(Commands::Watch(watch_args)) => {
+            watch::run(&mut c, &watch_args.dir, watch_args.interval, |c, url| process_url_with_hooks(c, url, &resolver, &config))?;
+        },
+        Some(Commands::Collection(collection_args)) => process_collection(&mut c, &collection_args.kinds, &config),
+        Some(Commands::Retag(retag_args)) => process_retag(&mut c, &retag_args.path, &config)?,
+        Some(Commands::Info(_)) | None => process_urls(&mut c, &config.urls, &resolver, &config),
     }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,135 @@
+use crate::api::structs::{Author, SearchResult};
+use crate::events::status;
+use crate::structs::{SearchItem, SearchType};
+use crate::urls::APP_URL;
+
+use serde_json::Error as SerdeErr;
+
+fn join_authors(authors: &[Author]) -> String {
+    authors.iter()
+        .flat_map(|author| author.persons.iter().map(|person| person.name.clone()))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn wanted(kind: SearchType, kind_filter: Option<SearchType>) -> bool {
+    kind_filter.is_none_or(|k| k == kind)
+}
+
+// Indices are 1-based and run across all types, in the order they're printed.
+pub fn flatten_results(res: SearchResult, kind_filter: Option<SearchType>) -> Vec<SearchItem> {
+    let mut items: Vec<SearchItem> = Vec::new();
+
+    if wanted(SearchType::Albums, kind_filter) {
+        for album in res.albums {
+            let subtitle = album.participants.iter()
+                .map(|p| p.name.clone())
+                .collect::<Vec<String>>()
+                .join(", ");
+            items.push(SearchItem {
+                index: items.len() + 1,
+                kind: SearchType::Albums,
+                title: album.title,
+                subtitle,
+                url: format!("{}albums/{}", APP_URL, album.slug),
+                slug: album.slug,
+            });
+        }
+    }
+
+    if wanted(SearchType::Recordings, kind_filter) {
+        for rec in res.recordings {
+            let mut title = rec.work.title.clone();
+            let composers = join_authors(&rec.work.authors);
+            if !composers.is_empty() {
+                title = format!("{}: {}", composers, title);
+            }
+            items.push(SearchItem {
+                index: items.len() + 1,
+                kind: SearchType::Recordings,
+                title,
+                subtitle: rec.summary,
+                url: format!("{}recordings/{}", APP_URL, rec.id),
+                slug: rec.id,
+            });
+        }
+    }
+
+    if wanted(SearchType::Works, kind_filter) {
+        for work in res.works {
+            items.push(SearchItem {
+                index: items.len() + 1,
+                kind: SearchType::Works,
+                title: work.title,
+                subtitle: join_authors(&work.authors),
+                url: format!("{}works/{}", APP_URL, work.id),
+                slug: work.id,
+            });
+        }
+    }
+
+    if wanted(SearchType::Artists, kind_filter) {
+        for artist in res.artists {
+            items.push(SearchItem {
+                index: items.len() + 1,
+                kind: SearchType::Artists,
+                title: artist.name,
+                subtitle: artist.functions.join(", "),
+                url: format!("{}profiles/{}/albums", APP_URL, artist.slug),
+                slug: artist.slug,
+            });
+        }
+    }
+
+    if wanted(SearchType::Playlists, kind_filter) {
+        for plist in res.playlists {
+            items.push(SearchItem {
+                index: items.len() + 1,
+                kind: SearchType::Playlists,
+                title: plist.title,
+                subtitle: plist.curator.map(|c| c.name).unwrap_or_default(),
+                url: format!("{}playlists/{}", APP_URL, plist.slug),
+                slug: plist.slug,
+            });
+        }
+    }
+
+    items
+}
+
+fn kind_heading(kind: SearchType) -> &'static str {
+    match kind {
+        SearchType::Albums => "Albums",
+        SearchType::Recordings => "Recordings",
+        SearchType::Works => "Works",
+        SearchType::Artists => "Artists",
+        SearchType::Playlists => "Playlists",
+    }
+}
+
+pub fn print_results(items: &[SearchItem]) {
+    if items.is_empty() {
+        status!("No results.");
+        return;
+    }
+
+    let mut last_kind: Option<SearchType> = None;
+    for item in items {
+        if last_kind != Some(item.kind) {
+            status!("{}:", kind_heading(item.kind));
+            last_kind = Some(item.kind);
+        }
+
+        if item.subtitle.is_empty() {
+            status!("{:>4}. {}", item.index, item.title);
+        } else {
+            status!("{:>4}. {} - {}", item.index, item.title, item.subtitle);
+        }
+        status!("      {} | {}", item.slug, item.url);
+    }
+}
+
+pub fn print_results_json(items: &[SearchItem]) -> Result<(), SerdeErr> {
+    println!("{}", serde_json::to_string_pretty(items)?);
+    Ok(())
+}
@@ -0,0 +1,324 @@
+use crate::api::client::IDAGIOClient;
+use crate::events::{self, Event, status};
+use crate::structs::{QueueItem, QueueState};
+use crate::utils;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+use serde::Deserialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const QUEUE_FNAME: &str = ".queue.json";
+
+static CANCEL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Deserialize)]
+struct EnqueueBody {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    urls: Vec<String>,
+}
+
+struct Queue {
+    items: Vec<QueueItem>,
+    next_id: u64,
+    path: PathBuf,
+}
+
+impl Queue {
+    fn load(path: PathBuf) -> Result<Queue, Box<dyn Error>> {
+        let mut items: Vec<QueueItem> = match File::open(&path) {
+            Ok(f) => serde_json::from_reader(f)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Whatever was running when we went down starts over.
+        for item in items.iter_mut() {
+            if item.state == QueueState::Running {
+                item.state = QueueState::Queued;
+            }
+        }
+
+        let next_id = items.iter().map(|i| i.id).max().unwrap_or(0) + 1;
+        Ok(Queue { items, next_id, path })
+    }
+
+    // Written to a temp file first so that a crash can't leave a truncated queue behind.
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let temp_path = utils::append_to_path(&self.path, ".tmp");
+        let f = File::create(&temp_path)?;
+        serde_json::to_writer_pretty(f, &self.items)?;
+        fs::rename(temp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn push(&mut self, url: &str) -> QueueItem {
+        let item = QueueItem {
+            id: self.next_id,
+            url: utils::clean_url(url),
+            state: QueueState::Queued,
+            error: None,
+            tracks: Vec::new(),
+            warnings: Vec::new(),
+        };
+        self.next_id += 1;
+        self.items.push(item.clone());
+        item
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut QueueItem> {
+        self.items.iter_mut().find(|i| i.id == id)
+    }
+}
+
+type SharedQueue = Arc<(Mutex<Queue>, Condvar)>;
+
+// A panic while the lock was held shouldn't take the whole server down with it.
+fn lock_queue(lock: &Mutex<Queue>) -> MutexGuard<'_, Queue> {
+    lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn cancel_requested() -> bool {
+    CANCEL_REQUESTED.load(Ordering::Relaxed)
+}
+
+fn save_or_warn(queue: &Queue) {
+    if let Err(e) = queue.save() {
+        events::warn(&format!("Failed to save queue.\n{}", e));
+    }
+}
+
+fn json_response<T: serde::Serialize>(value: &T, status_code: u16) -> Response<io::Cursor<Vec<u8>>> {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("static header is valid");
+    Response::from_data(body)
+        .with_status_code(status_code)
+        .with_header(header)
+}
+
+fn error_response(msg: &str, status_code: u16) -> Response<io::Cursor<Vec<u8>>> {
+    json_response(&serde_json::json!({ "error": msg }), status_code)
+}
+
+fn parse_item_id(path: &str) -> Option<u64> {
+    path.strip_prefix("/queue/")?.parse().ok()
+}
+
+fn handle_enqueue(req: &mut Request, shared: &SharedQueue) -> Response<io::Cursor<Vec<u8>>> {
+    let mut body = String::new();
+    if let Err(e) = req.as_reader().read_to_string(&mut body) {
+        return error_response(&e.to_string(), 400);
+    }
+
+    let parsed: EnqueueBody = match serde_json::from_str(&body) {
+        Ok(p) => p,
+        Err(e) => return error_response(&format!("invalid body: {}", e), 400),
+    };
+
+    let mut urls = parsed.urls;
+    urls.extend(parsed.url);
+    if urls.is_empty() {
+        return error_response("no urls given", 400);
+    }
+
+    let (lock, cvar) = &**shared;
+    let mut queue = lock_queue(lock);
+    let added: Vec<QueueItem> = urls.iter().map(|u| queue.push(u)).collect();
+    save_or_warn(&queue);
+    cvar.notify_one();
+
+    json_response(&added, 201)
+}
+
+fn handle_cancel(id: u64, shared: &SharedQueue) -> Response<io::Cursor<Vec<u8>>> {
+    let (lock, _) = &**shared;
+    let mut queue = lock_queue(lock);
+    let item = match queue.get_mut(id) {
+        Some(i) => i,
+        None => return error_response("no such item", 404),
+    };
+
+    match item.state {
+        QueueState::Queued => item.state = QueueState::Cancelled,
+        // The worker picks this up before the next track.
+        QueueState::Running => CANCEL_REQUESTED.store(true, Ordering::Relaxed),
+        _ => return error_response("item already finished", 409),
+    }
+
+    let item = item.clone();
+    save_or_warn(&queue);
+    json_response(&item, 200)
+}
+
+// Compares every byte whatever the input, so response times don't give away how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for (idx, byte) in b.iter().enumerate() {
+        diff |= (a.get(idx).copied().unwrap_or(0) ^ byte) as usize;
+    }
+    diff == 0
+}
+
+fn is_authorised(req: &Request, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(t) => t,
+        None => return true,
+    };
+    let expected = format!("Bearer {}", token);
+    req.headers().iter()
+        .filter(|h| h.field.equiv("Authorization"))
+        .any(|h| constant_time_eq(h.value.as_str().as_bytes(), expected.as_bytes()))
+}
+
+fn handle_request(mut req: Request, shared: &SharedQueue, token: Option<&str>) {
+    let method = req.method().clone();
+    let path = req.url().split('?').next().unwrap_or("").to_string();
+
+    let resp = match (&method, path.as_str()) {
+        _ if !is_authorised(&req, token) => error_response("unauthorised", 401),
+        (Method::Get, "/queue") => {
+            let (lock, _) = &**shared;
+            let queue = lock_queue(lock);
+            json_response(&queue.items, 200)
+        },
+        (Method::Post, "/queue") => handle_enqueue(&mut req, shared),
+        (Method::Get, p) if parse_item_id(p).is_some() => {
+            let id = parse_item_id(p).unwrap_or_default();
+            let (lock, _) = &**shared;
+            let mut queue = lock_queue(lock);
+            match queue.get_mut(id) {
+                Some(item) => json_response(item, 200),
+                None => error_response("no such item", 404),
+            }
+        },
+        (Method::Delete, p) if parse_item_id(p).is_some() => {
+            handle_cancel(parse_item_id(p).unwrap_or_default(), shared)
+        },
+        _ => error_response("not found", 404),
+    };
+
+    if let Err(e) = req.respond(resp) {
+        events::warn(&format!("Failed to send response.\n{}", e));
+    }
+}
+
+fn next_queued(shared: &SharedQueue) -> (u64, String) {
+    let (lock, cvar) = &**shared;
+    let mut queue = lock_queue(lock);
+    loop {
+        if let Some(item) = queue.items.iter_mut().find(|i| i.state == QueueState::Queued) {
+            item.state = QueueState::Running;
+            let next = (item.id, item.url.clone());
+            save_or_warn(&queue);
+            return next;
+        }
+        queue = cvar.wait(queue).unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+fn finish_item(shared: &SharedQueue, id: u64, res: Result<(), Box<dyn Error>>) {
+    let recorded = events::take_recorded();
+    let (lock, _) = &**shared;
+    let mut queue = lock_queue(lock);
+    let item = match queue.get_mut(id) {
+        Some(i) => i,
+        None => return,
+    };
+
+    for event in recorded {
+        match event {
+            Event::TrackDone { path, .. } => item.tracks.push(path),
+            Event::Warning { message } => item.warnings.push(message),
+            _ => {},
+        }
+    }
+
+    match res {
+        Ok(()) => item.state = QueueState::Done,
+        Err(_) if cancel_requested() => item.state = QueueState::Cancelled,
+        Err(e) => {
+            item.state = QueueState::Failed;
+            item.error = Some(e.to_string());
+        },
+    }
+
+    CANCEL_REQUESTED.store(false, Ordering::Relaxed);
+    save_or_warn(&queue);
+}
+
+// Anyone who can reach the port can queue downloads on the account, so no token means loopback only.
+fn is_loopback(bind: &str) -> Result<bool, Box<dyn Error>> {
+    let mut addrs = bind.to_socket_addrs()?.peekable();
+    if addrs.peek().is_none() {
+        return Err(format!("{} didn't resolve to any address", bind).into());
+    }
+    Ok(addrs.all(|a| a.ip().is_loopback()))
+}
+
+pub fn run<F>(c: &mut IDAGIOClient, out_path: &Path, bind: &str, token: Option<String>, mut process_url: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&mut IDAGIOClient, &str) -> Result<(), Box<dyn Error>>,
+{
+    if token.is_none() && !is_loopback(bind)? {
+        return Err("refusing to listen on a non-loopback address without --token".into());
+    }
+
+    let queue = Queue::load(out_path.join(QUEUE_FNAME))?;
+    let pending = queue.items.iter().filter(|i| i.state == QueueState::Queued).count();
+    if pending > 0 {
+        status!("Resuming queue with {} pending item(s).", pending);
+    }
+
+    let shared: SharedQueue = Arc::new((Mutex::new(queue), Condvar::new()));
+    let server = Server::http(bind).map_err(|e| format!("failed to bind to {}: {}", bind, e))?;
+    status!("Listening on http://{}", bind);
+
+    let server_shared = Arc::clone(&shared);
+    thread::spawn(move || {
+        for req in server.incoming_requests() {
+            handle_request(req, &server_shared, token.as_deref());
+        }
+    });
+
+    // The client isn't shared across threads, so all downloads happen here one at a time.
+    loop {
+        let (id, url) = next_queued(&shared);
+        status!("Queue item {}: {}", id, url);
+        events::emit(Event::UrlStart { url: url.clone(), url_num: id as usize, url_total: 0 });
+
+        events::start_recording();
+        let res = process_url(c, &url);
+        match &res {
+            Ok(()) => events::emit(Event::UrlDone { url: url.clone() }),
+            Err(e) => {
+                status!("URL failed.\n{}", e);
+                events::emit(Event::Error { source: url.clone(), message: e.to_string() });
+            },
+        }
+        finish_item(&shared, id, res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq(b"Bearer abc", b"Bearer abc"));
+        assert!(!constant_time_eq(b"Bearer abd", b"Bearer abc"));
+        assert!(!constant_time_eq(b"Bearer ab", b"Bearer abc"));
+        assert!(!constant_time_eq(b"Bearer abcd", b"Bearer abc"));
+        assert!(!constant_time_eq(b"", b"Bearer abc"));
+    }
+}
@@ -1,55 +1,353 @@
-use clap::Parser;
-use serde::Deserialize;
+use crate::transcode::Profile;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use clap::builder::RangedU64ValueParser;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 #[derive(Parser)]
-#[command(name = "IDAGIO Downloader")]
+#[command(name = "IDAGIO Downloader", subcommand_negates_reqs = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    #[clap(long, help="Download all of the account's personal playlists.")]
+    pub all_personal_playlists: bool,
+
+    #[clap(long, help="Skip artist albums whose recordings have all been downloaded already this run.")]
+    pub artist_dedup: bool,
+
+    #[clap(long, help="Skip compilations when downloading artist albums.")]
+    pub artist_exclude_compilations: bool,
+
+    #[clap(long, help="Download at most this many artist albums.")]
+    pub artist_max: Option<usize>,
+
+    #[clap(long, help="Only download artist albums with titles matching this regex.")]
+    pub artist_title_regex: Option<String>,
+
+    #[clap(long, help="Only download artist albums from this year or range, e.g. 1975 or 1970-1980.")]
+    pub artist_years: Option<String>,
+
+    #[clap(long, help="Extract booklet text into a .txt file next to the PDF.")]
+    pub booklet_text: bool,
+
+    #[clap(long, help="Max width/height of covers written to tracks. 0 = as served.")]
+    pub cover_embed_size: Option<u32>,
+
+    #[clap(long, help="Width/height of covers kept in album folders. 0 = as served.")]
+    pub cover_folder_size: Option<u32>,
+
+    #[clap(long, help="Write a CUE sheet to album folders.")]
+    pub cue_sheet: bool,
 
     #[clap(short='b', long, help="Download booklets when available.")]
     pub download_booklets: bool,
 
+    #[clap(short='n', long, help="Print what would be downloaded without writing anything.")]
+    pub dry_run: bool,
+
+    #[clap(long, help="Embed booklets into FLAC and MP4 tracks.")]
+    pub embed_booklets: bool,
+
     #[clap(short, long, help="1 = AAC 160 / 192, 2 = MP3 320 / AAC 320, 3 = 16/44 FLAC.")]
     pub format: Option<u8>,
 
     #[clap(short, long, help="Output path.")]
     pub out_path: Option<PathBuf>,
 
+    #[clap(long, help="Write an info.txt summary to album folders.")]
+    pub info_file: bool,
+
+    #[clap(long, help="Print one JSON object per event on stdout; other output goes to stderr.")]
+    pub json: bool,
+
+    #[clap(long, help="Save the full API metadata of albums, playlists and concerts as JSON.")]
+    pub meta_json: bool,
+
     #[clap(short, long, help="Keep covers in album folder.")]
     pub keep_covers: bool,
 
     #[clap(short, long, help="Write covers to tracks.")]
     pub write_covers: bool,
 
-    #[clap(short, long, num_args = 1.., required = true)]
+    #[clap(long, help="Tag playlist tracks with their source album's metadata instead of the playlist's.")]
+    pub plist_album_tags: bool,
+
+    #[clap(long, help="Write each playlist track's source album cover instead of the playlist's.")]
+    pub plist_track_covers: bool,
+
+    #[clap(long, value_enum, help="Analyse loudness after downloading and write ReplayGain or R128 gain tags.")]
+    pub replaygain: Option<LoudnessTags>,
+
+    #[clap(long, help="Also transcode downloads into a parallel folder per profile, e.g. \"opus 128\" or alac. Repeatable.")]
+    pub transcode: Vec<String>,
+
+    #[clap(short, long, num_args = 1.., required_unless_present = "all_personal_playlists")]
+    pub urls: Vec<String>,
+
+    #[clap(long, help="Only list work recordings with a conductor matching this.")]
+    pub work_conductor: Option<String>,
+
+    #[clap(long, help="Download the listed recordings of work URLs instead of just listing them.")]
+    pub work_download: bool,
+
+    #[clap(long, help="Only list work recordings with an ensemble matching this.")]
+    pub work_ensemble: Option<String>,
+
+    #[clap(long, default_value_t = 10, value_parser = RangedU64ValueParser::<usize>::new().range(1..), help="Number of recordings to list for work URLs.")]
+    pub work_top: usize,
+
+    #[clap(long, help="Only list work recordings from this year or range, e.g. 1975 or 1970-1980.")]
+    pub work_years: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    #[command(about="Search IDAGIO and optionally download results.")]
+    Search(SearchArgs),
+
+    #[command(about="Print resolved metadata for URLs without downloading. Same as --dry-run.")]
+    Info(InfoArgs),
+
+    #[command(about="Run a local HTTP API with a persistent download queue.")]
+    Serve(ServeArgs),
+
+    #[command(about="Watch a directory and download URLs from .txt files dropped into it.")]
+    Watch(WatchArgs),
+
+    #[command(about="Download the signed-in user's favourites.")]
+    Collection(CollectionArgs),
+
+    #[command(about="Rewrite the tags of already downloaded albums from fresh metadata.")]
+    Retag(RetagArgs),
+}
+
+#[derive(clap::Args)]
+pub struct RetagArgs {
+    #[clap(help="Album folder, or a folder of album folders.")]
+    pub path: PathBuf,
+}
+
+#[derive(clap::Args)]
+pub struct CollectionArgs {
+    #[clap(value_enum, help="What to download. Defaults to everything.")]
+    pub kinds: Vec<CollectionKind>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum CollectionKind {
+    Albums,
+    Tracks,
+    Recordings,
+    Playlists,
+    Artists,
+}
+
+#[derive(clap::Args)]
+pub struct WatchArgs {
+    #[clap(help="Directory to watch for .txt files of URLs.")]
+    pub dir: PathBuf,
+
+    #[clap(short, long, default_value_t = 5, help="Seconds between directory scans.")]
+    pub interval: u64,
+}
+
+#[derive(clap::Args)]
+pub struct ServeArgs {
+    #[clap(short, long, default_value = "127.0.0.1:8080", help="Address to listen on.")]
+    pub bind: String,
+
+    #[clap(short, long, help="Require this token as \"Authorization: Bearer <token>\". Needed to listen on non-loopback addresses.")]
+    pub token: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct InfoArgs {
+    #[clap(num_args = 1.., required = true)]
     pub urls: Vec<String>,
 }
 
+#[derive(clap::Args)]
+pub struct SearchArgs {
+    #[clap(help="Search query.")]
+    pub query: String,
+
+    #[clap(short, long, num_args = 1.., value_delimiter = ',', help="Download results by their index, e.g. 1,3.")]
+    pub download: Vec<usize>,
+
+    #[clap(short, long, help="Print results as JSON.")]
+    pub json: bool,
+
+    #[clap(short, long, default_value_t = 10, help="Max results per type.")]
+    pub limit: u32,
+
+    #[clap(short, long, value_enum, help="Only show results of this type.")]
+    pub r#type: Option<SearchType>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchType {
+    Albums,
+    Recordings,
+    Works,
+    Artists,
+    Playlists,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SearchItem {
+    pub index: usize,
+    pub kind: SearchType,
+    pub title: String,
+    pub subtitle: String,
+    pub slug: String,
+    pub url: String,
+}
+
 #[derive(Deserialize)]
 pub struct Config {
+    #[serde(skip_deserializing)]
+    pub all_personal_plists: bool,
+    #[serde(skip_deserializing)]
+    pub artist_opts: ArtistOptions,
+    #[serde(default = "default_booklet_template")]
+    pub booklet_template: String,
+    #[serde(default)]
+    pub booklet_text: bool,
+    #[serde(default)]
+    pub cover_embed_size: u32,
+    #[serde(default)]
+    pub cover_folder_size: u32,
+    #[serde(default)]
+    pub cue_sheet: bool,
     pub download_booklets: bool,
+    #[serde(skip_deserializing)]
+    pub dry_run: bool,
     pub email: String,
+    #[serde(default)]
+    pub embed_booklets: bool,
     #[serde(skip_deserializing)]
     pub ffmpeg_path: PathBuf,
     pub format: u8,
+    #[serde(default)]
+    pub info_file: bool,
     pub keep_covers: bool,
+    #[serde(default)]
+    pub meta_json: bool,
+    #[serde(default)]
+    pub on_album_complete: Option<String>,
+    #[serde(default)]
+    pub on_track_complete: Option<String>,
+    #[serde(default)]
+    pub on_url_failed: Option<String>,
     pub out_path: PathBuf,
     pub password: String,
+    #[serde(default)]
+    pub plist_album_tags: bool,
+    #[serde(default)]
+    pub plist_track_covers: bool,
+    #[serde(default)]
+    pub replaygain: Option<LoudnessTags>,
+    #[serde(default)]
+    pub transcode: Vec<String>,
+    #[serde(skip_deserializing)]
+    pub transcode_profiles: Vec<Profile>,
     #[serde(skip_deserializing)]
     pub urls: Vec<String>,
     pub use_ffmpeg_env_var: bool,
+    #[serde(skip_deserializing)]
+    pub work_opts: WorkOptions,
     pub write_covers: bool,
 }
 
+fn default_booklet_template() -> String {
+    "booklet".to_string()
+}
+
+#[derive(Default)]
+pub struct ArtistOptions {
+    pub dedup: bool,
+    pub exclude_compilations: bool,
+    pub max: Option<usize>,
+    pub title_regex: Option<Regex>,
+    pub year_from: u16,
+    pub year_to: u16,
+}
+
+#[derive(Default)]
+pub struct WorkOptions {
+    pub conductor: Option<String>,
+    pub download: bool,
+    pub ensemble: Option<String>,
+    pub top: usize,
+    pub year_from: u16,
+    pub year_to: u16,
+}
+
+#[derive(Clone, Default)]
 pub struct ParsedAlbumMeta {
+    pub album_id: String,
     pub album_title: String,
     pub album_artist: String,
     pub artist: String,
+    pub booklet_data: Vec<u8>,
     pub copyright: String,
     pub cover_data: Vec<u8>,
+    // Written as TXXX frames, freeform atoms or plain Vorbis comments.
+    pub custom_tags: Vec<(&'static str, String)>,
+    pub grouping: String,
+    pub isrc: String,
+    // album, playlist, recording or collection. Written as IDAGIO_SOURCE.
+    pub kind: &'static str,
+    pub recording_id: String,
+    pub source_url: String,
     pub title: String,
+    pub track_id: String,
     pub track_num: u16,
     pub track_total: u16,
     pub upc: String,
     pub year: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LoudnessTags {
+    // REPLAYGAIN_* gain and peak, relative to -18 LUFS.
+    Replaygain,
+    // R128_* gain in Q7.8, relative to -23 LUFS, as Opus players expect.
+    R128,
+}
+
+// What ended up on disk for each album track, for the CUE sheet and info file.
+pub struct AlbumTrackInfo {
+    pub artist: String,
+    pub duration: u32,
+    pub fname: String,
+    pub isrc: String,
+    pub quality: &'static str,
+    pub title: String,
+    pub track_num: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct QueueItem {
+    pub id: u64,
+    pub url: String,
+    pub state: QueueState,
+    pub error: Option<String>,
+    pub tracks: Vec<String>,
+    pub warnings: Vec<String>,
 }
\ No newline at end of file
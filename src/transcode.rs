@@ -0,0 +1,232 @@
+use crate::events::{self, status};
+use crate::utils;
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const COVER_FNAMES: [&str; 2] = ["folder.jpg", "folder.png"];
+
+#[derive(Clone, Copy)]
+enum Codec {
+    Aac,
+    Alac,
+    Flac,
+    Mp3,
+    Opus,
+}
+
+#[derive(Clone)]
+pub struct Profile {
+    // As written in the config, e.g. "opus 128". Also names the output tree.
+    pub name: String,
+    codec: Codec,
+    bitrate: u32,
+}
+
+impl Profile {
+    fn extension(&self) -> &'static str {
+        match self.codec {
+            Codec::Aac | Codec::Alac => "m4a",
+            Codec::Flac => "flac",
+            Codec::Mp3 => "mp3",
+            Codec::Opus => "opus",
+        }
+    }
+
+    fn codec_args(&self) -> Vec<String> {
+        let bitrate = format!("{}k", self.bitrate);
+        let args: Vec<&str> = match self.codec {
+            Codec::Aac => vec!["-c:a", "aac", "-b:a", &bitrate],
+            Codec::Alac => vec!["-c:a", "alac"],
+            Codec::Flac => vec!["-c:a", "flac"],
+            Codec::Mp3 => vec!["-c:a", "libmp3lame", "-b:a", &bitrate, "-id3v2_version", "3"],
+            Codec::Opus => vec!["-c:a", "libopus", "-b:a", &bitrate],
+        };
+        args.into_iter().map(|a| a.to_string()).collect()
+    }
+
+    // The Ogg muxer can't take embedded covers, so Opus copies only get folder.jpg.
+    fn keeps_embedded_cover(&self) -> bool {
+        !matches!(self.codec, Codec::Opus)
+    }
+
+    // The MP4 muxer drops any tag it has no atom for, e.g. IDAGIO_*, REPLAYGAIN_* and ISRC, unless told otherwise.
+    fn muxer_args(&self) -> &'static [&'static str] {
+        match self.codec {
+            Codec::Aac | Codec::Alac => &["-movflags", "+use_metadata_tags"],
+            _ => &[],
+        }
+    }
+}
+
+// e.g. "opus 128", "mp3 320", "aac 256", "alac" or "flac".
+pub fn parse_profile(profile: &str) -> Result<Profile, String> {
+    let name = profile.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let mut parts = name.split(' ');
+    let codec_str = parts.next().unwrap_or_default().to_lowercase();
+    let bitrate_str = parts.next();
+    if parts.next().is_some() {
+        return Err(format!("invalid transcode profile: {}", profile));
+    }
+
+    let (codec, default_bitrate) = match codec_str.as_str() {
+        "aac" => (Codec::Aac, 256),
+        "alac" => (Codec::Alac, 0),
+        "flac" => (Codec::Flac, 0),
+        "mp3" => (Codec::Mp3, 320),
+        "opus" => (Codec::Opus, 128),
+        _ => return Err(format!("unsupported transcode codec: {}", codec_str)),
+    };
+
+    let bitrate = match bitrate_str {
+        Some(_) if default_bitrate == 0 => return Err(format!("{} is lossless and takes no bitrate", codec_str)),
+        Some(b) => b.trim_end_matches(['k', 'K']).parse()
+            .map_err(|_| format!("invalid transcode bitrate: {}", b))?,
+        None => default_bitrate,
+    };
+
+    Ok(Profile { name, codec, bitrate })
+}
+
+fn run_ffmpeg(ffmpeg_path: &Path, in_path: &Path, out_path: &Path, profile: &Profile) -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::new(ffmpeg_path);
+    cmd.arg("-i")
+        .arg(in_path)
+        .args(["-map", "0:a", "-map_metadata", "0"]);
+    if profile.keeps_embedded_cover() {
+        cmd.args(["-map", "0:v?", "-c:v", "copy"]);
+    }
+
+    let output: Output = cmd
+        .args(profile.codec_args())
+        .args(profile.muxer_args())
+        .arg(out_path)
+        .stderr(Stdio::piped())
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let err_msg = format!("bad exit code, output: {}", stderr);
+        Err(err_msg)?
+    }
+    Ok(())
+}
+
+fn transcode_track(ffmpeg_path: &Path, track_path: &Path, dest_no_ext: &Path, profile: &Profile, refresh: bool) -> Result<(), Box<dyn Error>> {
+    let dest_path = utils::append_to_path(dest_no_ext, &format!(".{}", profile.extension()));
+    if !refresh && utils::file_exists(&dest_path)? {
+        return Ok(());
+    }
+
+    // FFmpeg picks the muxer from the extension, so it has to stay last.
+    let incomp_path = utils::append_to_path(dest_no_ext, &format!(".incomplete.{}", profile.extension()));
+    if utils::file_exists(&incomp_path)? {
+        fs::remove_file(&incomp_path)?;
+    }
+
+    if let Err(e) = run_ffmpeg(ffmpeg_path, track_path, &incomp_path, profile) {
+        let _ = fs::remove_file(&incomp_path);
+        return Err(e);
+    }
+    // Replaces the old copy when refreshing.
+    fs::rename(incomp_path, dest_path)?;
+    Ok(())
+}
+
+// "IDAGIO downloads" becomes "IDAGIO downloads (opus 128)" and so on, next to it.
+fn make_profile_root(out_path: &Path, profile: &Profile) -> PathBuf {
+    let mut root_name: OsString = out_path.file_name().unwrap_or_default().to_os_string();
+    root_name.push(format!(" ({})", profile.name));
+    out_path.with_file_name(root_name)
+}
+
+fn copy_covers(album_path: &Path, dest_dir: &Path) -> Result<(), Box<dyn Error>> {
+    for cover_fname in COVER_FNAMES {
+        let cover_path = album_path.join(cover_fname);
+        let dest_path = dest_dir.join(cover_fname);
+        if utils::file_exists(&cover_path)? && !utils::file_exists(&dest_path)? {
+            fs::copy(cover_path, dest_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn mirror_track(ffmpeg_path: &Path, track_path: &Path, dest_no_ext: &Path, profile: &Profile, refresh: bool) -> Result<(), Box<dyn Error>> {
+    let dest_dir = dest_no_ext.parent().ok_or("track has no parent folder")?;
+    fs::create_dir_all(dest_dir)?;
+    if let Some(album_path) = track_path.parent() {
+        copy_covers(album_path, dest_dir)?;
+    }
+    transcode_track(ffmpeg_path, track_path, dest_no_ext, profile, refresh)
+}
+
+// Failures only warn, as the masters are already safely on disk.
+// Existing copies are kept unless refresh is set, e.g. after the masters were retagged.
+pub fn transcode_tracks(ffmpeg_path: &Path, out_path: &Path, track_paths: &[PathBuf], profiles: &[Profile], refresh: bool) {
+    for profile in profiles {
+        status!("Transcoding to {}...", profile.name);
+        let profile_root = make_profile_root(out_path, profile);
+
+        for track_path in track_paths {
+            let rel_path = match track_path.strip_prefix(out_path) {
+                Ok(p) => p.with_extension(""),
+                Err(_) => continue,
+            };
+            let dest_no_ext = profile_root.join(&rel_path);
+            if let Err(e) = mirror_track(ffmpeg_path, track_path, &dest_no_ext, profile, refresh) {
+                events::warn(&format!("Failed to transcode {} to {}.\n{}", track_path.display(), profile.name, e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_profiles() {
+        let cases = [
+            ("opus 128", "opus 128", "opus", 128),
+            ("opus", "opus", "opus", 128),
+            ("  MP3   320k ", "MP3 320k", "mp3", 320),
+            ("aac 256K", "aac 256K", "m4a", 256),
+            ("aac", "aac", "m4a", 256),
+            ("alac", "alac", "m4a", 0),
+            ("flac", "flac", "flac", 0),
+        ];
+        for (input, name, extension, bitrate) in cases {
+            let profile = parse_profile(input).unwrap_or_else(|e| panic!("{}: {}", input, e));
+            assert_eq!(profile.name, name, "{}", input);
+            assert_eq!(profile.extension(), extension, "{}", input);
+            assert_eq!(profile.bitrate, bitrate, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_profiles() {
+        for input in ["", "wav", "opus fast", "opus 128 vbr", "alac 256", "flac 900"] {
+            assert!(parse_profile(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn mp4_copies_keep_custom_tags() {
+        for input in ["aac", "alac"] {
+            assert_eq!(parse_profile(input).unwrap().muxer_args(), ["-movflags", "+use_metadata_tags"], "{}", input);
+        }
+        for input in ["flac", "mp3", "opus"] {
+            assert!(parse_profile(input).unwrap().muxer_args().is_empty(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn profile_root_sits_next_to_out_path() {
+        let profile = parse_profile("opus 128").unwrap();
+        let root = make_profile_root(Path::new("/music/IDAGIO downloads"), &profile);
+        assert_eq!(root, Path::new("/music/IDAGIO downloads (opus 128)"));
+    }
+}
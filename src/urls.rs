@@ -0,0 +1,247 @@
+use std::error::Error;
+use std::fmt;
+
+use regex::Regex;
+use reqwest::Url;
+
+pub const APP_URL: &str = "https://app.idagio.com/";
+
+const HOSTS: [&str; 3] = ["app.idagio.com", "idagio.com", "www.idagio.com"];
+
+const SLUG_REGEX_STRING: &str = r#"^[a-zA-Z\d-]+$"#;
+const UUID_REGEX_STRING: &str = r#"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$"#;
+// e.g. "de" or "en-gb" in front of the real path.
+const LOCALE_REGEX_STRING: &str = r#"^[a-z]{2}(?:-[a-zA-Z]{2})?$"#;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MediaType {
+    Album,
+    Concert,
+    Playlist,
+    Artist,
+    PersonalPlaylist,
+    Recording,
+    Work,
+    Track,
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MediaType::Album => "album",
+            MediaType::Concert => "concert",
+            MediaType::Playlist => "playlist",
+            MediaType::Artist => "artist",
+            MediaType::PersonalPlaylist => "personal playlist",
+            MediaType::Recording => "recording",
+            MediaType::Work => "work",
+            MediaType::Track => "track",
+        };
+        f.write_str(name)
+    }
+}
+
+pub struct ResolvedUrl {
+    pub media_type: MediaType,
+    pub id: String,
+    // Only kept for artist URLs, where they're the site's album filters.
+    pub params: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum UrlError {
+    Unparseable(String),
+    NotIdagio(String),
+    UnknownPath(String),
+    MissingId(MediaType),
+    InvalidId(MediaType, String),
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UrlError::Unparseable(e) => write!(f, "couldn't parse as a URL: {}", e),
+            UrlError::NotIdagio(host) => write!(f, "{} isn't an IDAGIO host", host),
+            UrlError::UnknownPath(path) => write!(f, "unsupported IDAGIO page: {}", path),
+            UrlError::MissingId(media_type) => write!(f, "{} URL is missing its ID", media_type),
+            UrlError::InvalidId(media_type, id) => write!(f, "\"{}\" isn't a valid {} ID", id, media_type),
+        }
+    }
+}
+
+impl Error for UrlError {}
+
+pub struct UrlResolver {
+    slug_re: Regex,
+    uuid_re: Regex,
+    locale_re: Regex,
+}
+
+impl UrlResolver {
+    pub fn new() -> Result<UrlResolver, regex::Error> {
+        Ok(UrlResolver {
+            slug_re: Regex::new(SLUG_REGEX_STRING)?,
+            uuid_re: Regex::new(UUID_REGEX_STRING)?,
+            locale_re: Regex::new(LOCALE_REGEX_STRING)?,
+        })
+    }
+
+    fn check_id(&self, media_type: MediaType, id: Option<&&str>) -> Result<String, UrlError> {
+        let id = id.ok_or(UrlError::MissingId(media_type))?;
+        let re = if media_type == MediaType::PersonalPlaylist {
+            &self.uuid_re
+        } else {
+            &self.slug_re
+        };
+
+        if !re.is_match(id) {
+            return Err(UrlError::InvalidId(media_type, id.to_string()));
+        }
+        Ok(id.to_string())
+    }
+
+    pub fn resolve(&self, input: &str) -> Result<ResolvedUrl, UrlError> {
+        let trimmed = input.trim();
+
+        // Bare album UUIDs, as copied from the app's share sheet.
+        if self.uuid_re.is_match(trimmed) {
+            return Ok(ResolvedUrl { media_type: MediaType::Album, id: trimmed.to_string(), params: None });
+        }
+
+        let with_scheme = if trimmed.contains("://") {
+            trimmed.to_string()
+        } else {
+            format!("https://{}", trimmed)
+        };
+        let url = Url::parse(&with_scheme).map_err(|e| UrlError::Unparseable(e.to_string()))?;
+
+        if !["http", "https"].contains(&url.scheme()) {
+            return Err(UrlError::Unparseable(format!("unsupported scheme {}", url.scheme())));
+        }
+
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        if !HOSTS.contains(&host.as_str()) {
+            return Err(UrlError::NotIdagio(host));
+        }
+
+        let mut segments: Vec<&str> = url.path_segments()
+            .map(|s| s.filter(|seg| !seg.is_empty()).collect())
+            .unwrap_or_default();
+
+        if segments.len() > 1 && self.locale_re.is_match(segments[0]) {
+            segments.remove(0);
+        }
+
+        let unknown = || UrlError::UnknownPath(url.path().to_string());
+        let (media_type, id) = match segments.as_slice() {
+            ["albums", rest @ ..] => (MediaType::Album, rest.first()),
+            ["live", "event", rest @ ..] => (MediaType::Concert, rest.first()),
+            ["playlists", "personal", rest @ ..] => (MediaType::PersonalPlaylist, rest.first()),
+            ["playlists", rest @ ..] => (MediaType::Playlist, rest.first()),
+            ["profiles", rest @ ..] => {
+                if rest.len() > 2 || (rest.len() == 2 && !["about", "albums"].contains(&rest[1])) {
+                    return Err(unknown());
+                }
+                (MediaType::Artist, rest.first())
+            },
+            ["recordings", rest @ ..] => (MediaType::Recording, rest.first()),
+            ["works", rest @ ..] => (MediaType::Work, rest.first()),
+            ["tracks", rest @ ..] => (MediaType::Track, rest.first()),
+            _ => return Err(unknown()),
+        };
+        let id = self.check_id(media_type, id)?;
+
+        let params = if media_type == MediaType::Artist {
+            filter_query(&url)
+        } else {
+            None
+        };
+
+        Ok(ResolvedUrl { media_type, id, params })
+    }
+}
+
+// Drops tracking params like utm_source so they don't end up in the artist filters.
+fn filter_query(url: &Url) -> Option<String> {
+    let query = url.query()?;
+    let kept: Vec<&str> = query.split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("utm_"))
+        .collect();
+
+    if kept.is_empty() {
+        return None;
+    }
+    Some(kept.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALBUM_UUID: &str = "1628a93d-cfdc-4850-bda1-3b14209f729b";
+
+    fn resolve(input: &str) -> Result<ResolvedUrl, UrlError> {
+        UrlResolver::new().unwrap().resolve(input)
+    }
+
+    #[test]
+    fn resolves_accepted_forms() {
+        let cases = [
+            ("https://app.idagio.com/albums/brahms-4", MediaType::Album, "brahms-4", None),
+            ("http://app.idagio.com/albums/brahms-4", MediaType::Album, "brahms-4", None),
+            ("https://www.idagio.com/albums/brahms-4", MediaType::Album, "brahms-4", None),
+            ("https://idagio.com/albums/brahms-4/", MediaType::Album, "brahms-4", None),
+            ("app.idagio.com/albums/brahms-4", MediaType::Album, "brahms-4", None),
+            ("  https://APP.IDAGIO.COM/albums/brahms-4  ", MediaType::Album, "brahms-4", None),
+            ("https://app.idagio.com/de/albums/brahms-4", MediaType::Album, "brahms-4", None),
+            ("https://app.idagio.com/en-gb/albums/brahms-4", MediaType::Album, "brahms-4", None),
+            ("https://app.idagio.com/albums/brahms-4?utm_source=share", MediaType::Album, "brahms-4", None),
+            (ALBUM_UUID, MediaType::Album, ALBUM_UUID, None),
+            ("https://app.idagio.com/live/event/ercole-amante", MediaType::Concert, "ercole-amante", None),
+            ("https://app.idagio.com/playlists/top-five", MediaType::Playlist, "top-five", None),
+            ("https://app.idagio.com/playlists/personal/3da14c0c-0a53-4131-946f-1920d3203ad7", MediaType::PersonalPlaylist, "3da14c0c-0a53-4131-946f-1920d3203ad7", None),
+            ("https://app.idagio.com/profiles/claudio-monteverdi", MediaType::Artist, "claudio-monteverdi", None),
+            ("https://app.idagio.com/profiles/claudio-monteverdi/about", MediaType::Artist, "claudio-monteverdi", None),
+            ("https://app.idagio.com/profiles/claudio-monteverdi/albums?sort=newest", MediaType::Artist, "claudio-monteverdi", Some("sort=newest")),
+            ("https://app.idagio.com/profiles/claudio-monteverdi/albums?utm_source=x&sort=newest&utm_medium=y", MediaType::Artist, "claudio-monteverdi", Some("sort=newest")),
+            ("https://app.idagio.com/profiles/claudio-monteverdi/albums?utm_source=x", MediaType::Artist, "claudio-monteverdi", None),
+            ("https://app.idagio.com/recordings/23620456", MediaType::Recording, "23620456", None),
+            ("https://app.idagio.com/works/8157", MediaType::Work, "8157", None),
+            ("https://app.idagio.com/tracks/123", MediaType::Track, "123", None),
+        ];
+
+        for (input, media_type, id, params) in cases {
+            let resolved = resolve(input).unwrap_or_else(|e| panic!("{}: {}", input, e));
+            assert_eq!(resolved.media_type, media_type, "{}", input);
+            assert_eq!(resolved.id, id, "{}", input);
+            assert_eq!(resolved.params.as_deref(), params, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let cases = [
+            "ftp://app.idagio.com/albums/brahms-4",
+            "https://example.com/albums/brahms-4",
+            "https://app.idagio.com/",
+            "https://app.idagio.com/discover",
+            "https://app.idagio.com/albums",
+            "https://app.idagio.com/albums/bad_slug!",
+            "https://app.idagio.com/playlists/personal/not-a-uuid",
+            "https://app.idagio.com/profiles/claudio-monteverdi/works",
+            "https://app.idagio.com/profiles/claudio-monteverdi/albums/extra",
+            "https://[::1",
+        ];
+
+        for input in cases {
+            assert!(resolve(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn track_urls_resolve_so_they_can_be_rejected_by_name() {
+        let resolved = resolve("https://app.idagio.com/de/tracks/abc-1").unwrap();
+        assert_eq!(resolved.media_type, MediaType::Track);
+        assert_eq!(resolved.media_type.to_string(), "track");
+    }
+}
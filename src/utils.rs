@@ -1,7 +1,7 @@
 use std::error::Error;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Error as IoError};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
 
 pub fn get_exe_path() -> Result<PathBuf, Box<dyn Error>> {
@@ -40,20 +40,26 @@ fn read_text_file_lines(filename: &str) -> Result<Vec<String>, IoError> {
 
 pub fn clean_url(url: &str) -> String {
     let trimmed = url.trim();
-    let stripped = trimmed.strip_suffix('/').unwrap_or(&trimmed);
+    let stripped = trimmed.strip_suffix('/').unwrap_or(trimmed);
     stripped.to_string()
 }
 
+// Case-insensitive, so LIST.TXT is read rather than taken as a URL.
+pub fn is_text_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"))
+}
+
 pub fn process_urls(urls: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
     let mut processed: Vec<String> = Vec::new();
     let mut text_paths: Vec<String> = Vec::new();
 
     for url in urls {
-        if url.ends_with(".txt") {
-            if contains(&text_paths, &url) {
+        if is_text_file(Path::new(url)) {
+            if contains(&text_paths, url) {
                 continue;
             }
-            let text_lines = read_text_file_lines(&url)?;
+            let text_lines = read_text_file_lines(url)?;
             for text_line in text_lines {
                 let cleaned_line = clean_url(&text_line);
                 if !contains(&processed, &cleaned_line) {
@@ -62,7 +68,7 @@ pub fn process_urls(urls: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
             }
             text_paths.push(url.clone());
         } else {
-            let cleaned_line = clean_url(&url);
+            let cleaned_line = clean_url(url);
             if !contains(&processed, &cleaned_line) {
                 processed.push(cleaned_line);
             }
@@ -72,7 +78,7 @@ pub fn process_urls(urls: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
     Ok(processed)
 }
 
-pub fn file_exists(file_path: &PathBuf) -> Result<bool, IoError> {
+pub fn file_exists(file_path: &Path) -> Result<bool, IoError> {
     match fs::metadata(file_path) {
         Ok(meta) => Ok(meta.is_file()),
         Err(err) => {
@@ -85,14 +91,63 @@ pub fn file_exists(file_path: &PathBuf) -> Result<bool, IoError> {
     }
 }
 
-pub fn append_to_path(path: &PathBuf, to_append: &str) -> PathBuf {
+pub fn file_size(file_path: &Path) -> Result<u64, IoError> {
+    match fs::metadata(file_path) {
+        Ok(meta) => Ok(meta.len()),
+        Err(err) => {
+            if err.kind() == io::ErrorKind::NotFound {
+                Ok(0)
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+pub fn append_to_path(path: &Path, to_append: &str) -> PathBuf {
     let path_str = path.to_string_lossy();
     let new_path_str = format!("{}{}", path_str, to_append);
     PathBuf::from(new_path_str)
 }
 
-pub fn set_path_ext(path: &PathBuf, ext: &str) -> PathBuf {
-    let mut new_path = path.clone();
+pub fn set_path_ext(path: &Path, ext: &str) -> PathBuf {
+    let mut new_path = path.to_path_buf();
     new_path.set_extension(ext);
     new_path
+}
+
+// "1975" or "1970-1980", inclusive.
+pub fn parse_year_range(years: &str) -> Result<(u16, u16), Box<dyn Error>> {
+    let (from_str, to_str) = years.split_once('-').unwrap_or((years, years));
+    let from: u16 = from_str.trim().parse()?;
+    let to: u16 = to_str.trim().parse()?;
+    if from > to {
+        return Err("start year is after end year".into());
+    }
+    Ok((from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_year_ranges() {
+        let cases = [
+            ("1975", (1975, 1975)),
+            ("1970-1980", (1970, 1980)),
+            (" 1970 - 1980 ", (1970, 1980)),
+            ("1980-1980", (1980, 1980)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_year_range(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_year_ranges() {
+        for input in ["", "19x5", "1980-1970", "1970-", "-1980", "1970-1980-1990"] {
+            assert!(parse_year_range(input).is_err(), "{}", input);
+        }
+    }
 }
\ No newline at end of file
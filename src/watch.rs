@@ -0,0 +1,143 @@
+use crate::api::client::IDAGIOClient;
+use crate::events::{self, Event, status};
+use crate::utils;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DONE_DIR_NAME: &str = "done";
+const FAILED_DIR_NAME: &str = "failed";
+
+fn list_text_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if utils::is_text_file(&path) && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+// Don't clobber an earlier file of the same name; prefix with the time instead.
+fn unique_dest(dir: &Path, fname: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let dest = dir.join(fname);
+    if !utils::file_exists(&dest)? {
+        return Ok(dest);
+    }
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(dir.join(format!("{}_{}", secs, fname)))
+}
+
+fn write_log(dest_path: &Path, log_lines: &[String]) -> Result<(), Box<dyn Error>> {
+    let log_path = utils::set_path_ext(dest_path, "log");
+    let mut f = File::create(log_path)?;
+    for line in log_lines {
+        writeln!(f, "{}", line)?;
+    }
+    Ok(())
+}
+
+// Keeps a file that couldn't be read from being retried on every poll.
+fn move_to_failed(txt_path: &Path, dir: &Path, err_str: &str) -> Result<(), Box<dyn Error>> {
+    if !utils::file_exists(txt_path)? {
+        return Ok(());
+    }
+    let fname = txt_path.file_name()
+        .ok_or("text file has no file name")?
+        .to_string_lossy()
+        .to_string();
+    let dest_path = unique_dest(&dir.join(FAILED_DIR_NAME), &fname)?;
+    fs::rename(txt_path, &dest_path)?;
+    write_log(&dest_path, &[format!("FAILED {}", err_str)])
+}
+
+fn process_text_file<F>(c: &mut IDAGIOClient, txt_path: &Path, dir: &Path, process_url: &mut F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&mut IDAGIOClient, &str) -> Result<(), Box<dyn Error>>,
+{
+    let fname = txt_path.file_name()
+        .ok_or("text file has no file name")?
+        .to_string_lossy()
+        .to_string();
+    status!("Processing {}...", fname);
+
+    let txt_path_string = txt_path.to_string_lossy().to_string();
+    let urls = utils::process_urls(&[txt_path_string])?;
+    let url_total = urls.len();
+
+    let mut log_lines: Vec<String> = Vec::new();
+    let mut failed = false;
+    for (mut url_num, url) in urls.iter().enumerate() {
+        url_num += 1;
+        status!("URL {} of {}:", url_num, url_total);
+        events::emit(Event::UrlStart { url: url.clone(), url_num, url_total });
+        match process_url(c, url) {
+            Ok(()) => {
+                events::emit(Event::UrlDone { url: url.clone() });
+                log_lines.push(format!("OK     {}", url));
+            },
+            Err(e) => {
+                status!("URL failed.\n{}", e);
+                events::emit(Event::Error { source: url.clone(), message: e.to_string() });
+                log_lines.push(format!("FAILED {}\n       {}", url, e.to_string().replace('\n', "\n       ")));
+                failed = true;
+            },
+        }
+    }
+
+    let dest_dir = dir.join(if failed { FAILED_DIR_NAME } else { DONE_DIR_NAME });
+    let dest_path = unique_dest(&dest_dir, &fname)?;
+    fs::rename(txt_path, &dest_path)?;
+
+    write_log(&dest_path, &log_lines)?;
+
+    status!("Moved {} to {}.\n", fname, dest_dir.display());
+    Ok(())
+}
+
+pub fn run<F>(c: &mut IDAGIOClient, dir: &Path, interval: u64, mut process_url: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&mut IDAGIOClient, &str) -> Result<(), Box<dyn Error>>,
+{
+    fs::create_dir_all(dir.join(DONE_DIR_NAME))?;
+    fs::create_dir_all(dir.join(FAILED_DIR_NAME))?;
+    status!("Watching {} for .txt files...", dir.display());
+
+    // A file is only picked up once its size has stayed the same for a whole interval,
+    // so we don't read lists that are still being copied in.
+    let mut seen_sizes: HashMap<PathBuf, u64> = HashMap::new();
+    // Files that couldn't be moved out of the way aren't retried until the next start.
+    let mut stuck: HashSet<PathBuf> = HashSet::new();
+    loop {
+        let paths = list_text_files(dir)?;
+        seen_sizes.retain(|p, _| paths.contains(p));
+
+        for path in paths {
+            if stuck.contains(&path) {
+                continue;
+            }
+            let size = utils::file_size(&path)?;
+            if seen_sizes.insert(path.clone(), size) != Some(size) {
+                continue;
+            }
+            seen_sizes.remove(&path);
+
+            if let Err(e) = process_text_file(c, &path, dir, &mut process_url) {
+                events::warn(&format!("Failed to process {}.\n{}", path.display(), e));
+                if let Err(e) = move_to_failed(&path, dir, &e.to_string()) {
+                    events::warn(&format!("Failed to move {} to {}.\n{}", path.display(), FAILED_DIR_NAME, e));
+                    stuck.insert(path);
+                }
+            }
+        }
+
+        thread::sleep(Duration::from_secs(interval));
+    }
+}